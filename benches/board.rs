@@ -0,0 +1,110 @@
+// The game lives in a binary crate, so pull the modules in directly
+#[allow(dead_code, unused_imports)]
+#[path = "../src/game/mod.rs"]
+mod game;
+mod legacy;
+#[allow(dead_code)]
+#[path = "../src/utils.rs"]
+mod utils;
+
+use std::hint::black_box;
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use maud::Markup;
+use tokio::runtime::Runtime;
+
+use crate::game::{Board, BoardBuilder, Point, ShipDefinition};
+
+// The old model is async all the way down
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+}
+
+fn flat_board(ships: &[ShipDefinition]) -> Board {
+    BoardBuilder::rectangle(10, 10)
+        .random(ships, &mut rand::rng())
+        .unwrap()
+}
+
+fn generation(c: &mut Criterion) {
+    let ships = ShipDefinition::classic();
+    let runtime = runtime();
+    let mut group = c.benchmark_group("generate 10x10");
+
+    // Not quite the same work: the flat board runs the backtracking search,
+    // the old one dropped ships on random cells until they fit
+
+    group.bench_function("flat", |b| b.iter(|| black_box(flat_board(&ships))));
+    group.bench_function("arc rwlock", |b| {
+        b.iter(|| black_box(runtime.block_on(legacy::random(10)).unwrap()))
+    });
+    group.finish();
+}
+
+fn rendering(c: &mut Criterion) {
+    let ships = ShipDefinition::classic();
+    let runtime = runtime();
+    let mut group = c.benchmark_group("render 10x10");
+
+    let target = flat_board(&ships);
+    let fleet = flat_board(&ships);
+    group.bench_function("flat", |b| {
+        b.iter(|| black_box(target.render(&fleet, None, None, None, None, Markup::default())))
+    });
+
+    let target = runtime.block_on(legacy::random(10)).unwrap();
+    let fleet = runtime.block_on(legacy::random(10)).unwrap();
+    group.bench_function("arc rwlock", |b| {
+        b.iter(|| black_box(runtime.block_on(target.render(&fleet))))
+    });
+    group.finish();
+}
+
+fn playing(c: &mut Criterion) {
+    let ships = ShipDefinition::classic();
+    let runtime = runtime();
+    let mut group = c.benchmark_group("shoot every cell 10x10");
+
+    let points: Vec<Point> = (0..10)
+        .flat_map(|x| (0..10).map(move |y| format!("{x}-{y}").parse().unwrap()))
+        .collect();
+    // Boards are generated outside of the measurement, the two models place ships differently
+    group.bench_function("flat", |b| {
+        b.iter_batched(
+            || flat_board(&ships),
+            |mut board| {
+                for &point in &points {
+                    // Cells around sunk ships are already exposed, those shots fail.
+                    // Run with RUST_BACKTRACE unset, or every failed shot captures a backtrace.
+                    let _ = black_box(board.hit(point));
+                }
+                board.is_win()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    let points: Vec<legacy::Point> = (0..10)
+        .flat_map(|x| (0..10).map(move |y| legacy::Point::new(x, y)))
+        .collect();
+    group.bench_function("arc rwlock", |b| {
+        b.iter_batched(
+            || runtime.block_on(legacy::random(10)).unwrap(),
+            |mut board| {
+                runtime.block_on(async {
+                    for &point in &points {
+                        let _ = black_box(board.hit(point).await);
+                    }
+                    board.is_win().await
+                })
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, generation, rendering, playing);
+criterion_main!(benches);
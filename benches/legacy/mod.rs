@@ -0,0 +1,292 @@
+//! The board as it was before the flat model: cells and ships behind `Arc<RwLock>`,
+//! pointing at each other. Kept only so the benchmark can compare both.
+
+use std::{collections::HashSet, sync::Arc};
+
+use maud::{Markup, html};
+use rand::Rng;
+use tokio::sync::RwLock;
+
+type Dyn<T> = Arc<RwLock<T>>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Point {
+    x: u8,
+    y: u8,
+}
+
+impl Point {
+    pub fn new(x: u8, y: u8) -> Self {
+        Self { x, y }
+    }
+
+    fn try_add_delta(&self, dx: isize, dy: isize) -> Option<Self> {
+        Some(Point {
+            x: (self.x as isize + dx).try_into().ok()?,
+            y: (self.y as isize + dy).try_into().ok()?,
+        })
+    }
+}
+
+enum CellContent {
+    Water,
+    // Never read, but held just like the old halo cells did
+    #[allow(dead_code)]
+    NearShip(Dyn<Ship>),
+    Ship(Dyn<Ship>),
+}
+
+struct CellState {
+    content: CellContent,
+    exposed: bool,
+}
+
+impl CellState {
+    fn render(&self, point: Point, target: bool) -> Markup {
+        let ship = matches!(self.content, CellContent::Ship(_));
+        let class = match (target, self.exposed, ship) {
+            (true, false, _) => "cell active",
+            (false, false, false) => "cell idle",
+            (false, false, true) => "cell ship",
+            (false, true, true) => "cell ship hit",
+            (true, true, true) => "cell ship",
+            (_, true, false) => "cell water",
+        };
+        let id = format!("{}-{}", point.x, point.y);
+
+        html!({ div id=(id) class=(class) {} })
+    }
+}
+
+struct Ship {
+    length: u8,
+    nearby_cells: Vec<Dyn<CellState>>,
+    counter: Dyn<ShipCounter>,
+}
+
+impl Ship {
+    async fn hit(&mut self) {
+        let Some(length) = self.length.checked_sub(1) else {
+            return;
+        };
+        self.length = length;
+
+        if self.length == 0 {
+            self.counter.write().await.remaining -= 1;
+            for cell in &self.nearby_cells {
+                cell.write().await.exposed = true;
+            }
+        }
+    }
+}
+
+struct ShipCounter {
+    name: String,
+    total: u8,
+    remaining: u8,
+}
+
+pub struct Board {
+    ships: Vec<Dyn<Ship>>,
+    ship_counters: Vec<Dyn<ShipCounter>>,
+    state: Vec<Vec<Dyn<CellState>>>,
+    shots: Vec<Point>,
+}
+
+impl Board {
+    fn get_cell(&self, point: Point) -> Option<Dyn<CellState>> {
+        self.state
+            .get(point.x as usize)?
+            .get(point.y as usize)
+            .cloned()
+    }
+
+    pub async fn hit(&mut self, point: Point) -> Result<(), ()> {
+        let cell = self.get_cell(point).ok_or(())?;
+        let mut cell = cell.write().await;
+
+        if cell.exposed {
+            return Err(());
+        }
+        cell.exposed = true;
+        self.shots.push(point);
+
+        if let CellContent::Ship(ship) = &cell.content {
+            ship.write().await.hit().await;
+        }
+        Ok(())
+    }
+
+    pub async fn is_win(&self) -> bool {
+        for counter in &self.ship_counters {
+            if counter.read().await.remaining > 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    async fn render_grid(&self, id: &str, target: bool) -> Markup {
+        let mut cells = Vec::new();
+        for (x, row) in self.state.iter().enumerate() {
+            for (y, cell) in row.iter().enumerate() {
+                let point = Point::new(x as u8, y as u8);
+                cells.push(cell.read().await.render(point, target));
+            }
+        }
+
+        html! {
+            .board id=(id) {
+                @for cell in cells {
+                    (cell)
+                }
+            }
+        }
+    }
+
+    pub async fn render(&self, fleet: &Board) -> Markup {
+        let mut counters = Vec::new();
+        for counter in &self.ship_counters {
+            let counter = counter.read().await;
+            counters.push(html!({
+                .ship-counter {
+                    .cnt-name {(counter.name)}
+                    .cnt-row {(counter.remaining) "/" (counter.total)}
+                }
+            }));
+        }
+
+        html! {
+            #stats-container {
+                @for counter in counters {
+                    (counter)
+                }
+            }
+            (self.render_grid("board", true).await)
+            (fleet.render_grid("fleet-board", false).await)
+        }
+    }
+}
+
+/// Classic fleet as (name, length, count)
+pub const CLASSIC: [(&str, u8, u8); 4] = [
+    ("Линкор", 4, 1),
+    ("Крейсер", 3, 2),
+    ("Эсминец", 2, 3),
+    ("Торпеда", 1, 4),
+];
+
+/// Places ships one at a time on random cells, the way boards used to be generated
+pub async fn random(n: u8) -> Option<Board> {
+    let mut board = Board {
+        ships: Vec::new(),
+        ship_counters: Vec::new(),
+        state: (0..n)
+            .map(|_| {
+                (0..n)
+                    .map(|_| {
+                        Arc::new(RwLock::new(CellState {
+                            content: CellContent::Water,
+                            exposed: false,
+                        }))
+                    })
+                    .collect()
+            })
+            .collect(),
+        shots: Vec::new(),
+    };
+
+    for (name, length, count) in CLASSIC {
+        let counter = Arc::new(RwLock::new(ShipCounter {
+            name: name.to_string(),
+            total: count,
+            remaining: count,
+        }));
+        board.ship_counters.push(counter.clone());
+
+        for _ in 0..count {
+            add_ship_random(&mut board, &counter, n, length).await?;
+        }
+    }
+    Some(board)
+}
+
+async fn add_ship_random(
+    board: &mut Board,
+    counter: &Dyn<ShipCounter>,
+    n: u8,
+    length: u8,
+) -> Option<()> {
+    const TRIES: u16 = 1000;
+
+    for _ in 0..TRIES {
+        let vertical = rand::rng().random_bool(0.5);
+        let (dx, dy) = match vertical {
+            true => (length - 1, 0),
+            false => (0, length - 1),
+        };
+
+        let start = Point::new(
+            rand::rng().random_range(0..n.saturating_sub(dx)),
+            rand::rng().random_range(0..n.saturating_sub(dy)),
+        );
+        let points: Vec<Point> = (0..length)
+            .map(|i| match vertical {
+                true => Point::new(start.x + i, start.y),
+                false => Point::new(start.x, start.y + i),
+            })
+            .collect();
+
+        if add_ship(board, counter, &points).await {
+            return Some(());
+        }
+    }
+    None
+}
+
+async fn add_ship(board: &mut Board, counter: &Dyn<ShipCounter>, points: &[Point]) -> bool {
+    let mut ship_cells = Vec::new();
+    let mut near_cells = Vec::new();
+
+    for &point in points {
+        let Some(cell) = board.get_cell(point) else {
+            return false;
+        };
+
+        if !matches!(cell.read().await.content, CellContent::Water) {
+            return false;
+        }
+
+        let mut tried = HashSet::new();
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                if let Some(near) = point.try_add_delta(dx, dy)
+                    && !points.contains(&near)
+                    && tried.insert(near)
+                    && let Some(cell) = board.get_cell(near)
+                {
+                    if matches!(cell.read().await.content, CellContent::Ship(_)) {
+                        return false;
+                    }
+                    near_cells.push(cell);
+                }
+            }
+        }
+        ship_cells.push(cell);
+    }
+
+    let ship = Arc::new(RwLock::new(Ship {
+        length: points.len() as u8,
+        nearby_cells: near_cells.clone(),
+        counter: counter.clone(),
+    }));
+    board.ships.push(ship.clone());
+
+    for cell in ship_cells {
+        cell.write().await.content = CellContent::Ship(ship.clone());
+    }
+    for cell in near_cells {
+        cell.write().await.content = CellContent::NearShip(ship.clone());
+    }
+    true
+}
@@ -0,0 +1,30 @@
+// Listens to the game event stream while a game screen is shown
+// and applies what the server pushes without waiting for a click
+htmx.defineExtension("events", {
+    onEvent: function (name) {
+        if (name !== "htmx:load") return;
+
+        const screen = document.querySelector("[data-events]");
+        const url = screen ? screen.getAttribute("data-events") : null;
+
+        if (this.source && this.source.url.endsWith(url)) return;
+
+        if (this.source) {
+            this.source.close();
+            this.source = null;
+        }
+
+        if (!url) return;
+
+        this.source = new EventSource(url);
+
+        // Out-of-band fragments, swapped in by their ids
+        this.source.addEventListener("update", function (event) {
+            htmx.swap(document.body, event.data, { swapStyle: "none" });
+        });
+
+        this.source.addEventListener("refresh", function () {
+            htmx.ajax("GET", "/game", { target: "#screen" });
+        });
+    },
+});
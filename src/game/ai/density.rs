@@ -0,0 +1,109 @@
+use rand::seq::IndexedRandom;
+
+use crate::game::{
+    Point, Shape,
+    ai::{Knowledge, Sight, Strategy},
+};
+
+/// Enumerates every legal placement of the remaining ships
+/// and shoots at the cell covered by the most of them
+pub struct Density;
+
+impl Density {
+    // Returns None if the ship can't be there, otherwise the number of wounded cells it covers
+    fn placement(sight: &Sight, points: &[Point]) -> Option<usize> {
+        let mut hits = 0;
+
+        for &point in points {
+            match sight.get(point)? {
+                Knowledge::Unknown => {}
+                Knowledge::Hit => hits += 1,
+                Knowledge::Miss | Knowledge::Sunk => return None,
+            }
+
+            if sight.ships_touch {
+                continue;
+            }
+
+            // Ships never touch, so any other wounded cell nearby belongs to this ship
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    let Some(near) = point.try_add_delta(dx, dy) else {
+                        continue;
+                    };
+
+                    if !points.contains(&near)
+                        && matches!(sight.get(near), Some(Knowledge::Hit | Knowledge::Sunk))
+                    {
+                        return None;
+                    }
+                }
+            }
+        }
+        Some(hits)
+    }
+
+    // Placements covering each cell, laid out the same way as the board cells
+    fn heatmap(sight: &Sight) -> Vec<usize> {
+        let targeting = !sight.with(Knowledge::Hit).is_empty();
+        let mut heat = vec![0; sight.cells.len()];
+
+        // Ships of the same shape share their placements
+        let mut shapes: Vec<(&Shape, usize)> = Vec::new();
+        for shape in &sight.remaining {
+            match shapes.iter_mut().find(|(other, _)| *other == shape) {
+                Some((_, count)) => *count += 1,
+                None => shapes.push((shape, 1)),
+            }
+        }
+
+        for (shape, count) in shapes {
+            let variants = shape.variants();
+
+            for start in sight.points() {
+                for variant in &variants {
+                    let Some(points) = variant.points(start) else {
+                        continue;
+                    };
+
+                    let weight = match Self::placement(sight, &points) {
+                        None => continue,
+                        // While finishing off a wounded ship, only placements through it matter
+                        Some(0) if targeting => continue,
+                        Some(0) => 1,
+                        Some(hits) => hits,
+                    };
+
+                    for &point in &points {
+                        if let Some(index) = sight.index(point) {
+                            heat[index] += weight * count;
+                        }
+                    }
+                }
+            }
+        }
+        heat
+    }
+}
+
+impl Strategy for Density {
+    fn target(&mut self, sight: &Sight) -> Option<Point> {
+        let heat = Self::heatmap(sight);
+        let unknown = sight.with(Knowledge::Unknown);
+
+        // Wounded cells are covered too, but there is no point shooting at them
+        let score = |point: &Point| sight.index(*point).map_or(0, |index| heat[index]);
+        let max = unknown.iter().map(score).max()?;
+
+        if max == 0 {
+            return sight.random_unknown();
+        }
+
+        let best: Vec<Point> = unknown
+            .into_iter()
+            .filter(|point| score(point) == max)
+            .collect();
+
+        best.choose(&mut rand::rng()).copied()
+    }
+}
@@ -0,0 +1,92 @@
+use rand::seq::IndexedRandom;
+
+use crate::game::{
+    Point, Shape,
+    ai::{Knowledge, Sight, Strategy},
+};
+
+/// Shoots on a checkerboard until it hits something,
+/// then finishes the wounded ship off along its axis
+pub struct HuntTarget;
+
+impl HuntTarget {
+    // Cells that may continue the wounded ship containing `hit`
+    fn follow_up(sight: &Sight, hit: Point) -> Vec<Point> {
+        let is_hit = |point: Option<Point>| {
+            point.is_some_and(|point| sight.get(point) == Some(Knowledge::Hit))
+        };
+
+        let horizontal = is_hit(hit.try_add_delta(0, -1)) || is_hit(hit.try_add_delta(0, 1));
+        let vertical = is_hit(hit.try_add_delta(-1, 0)) || is_hit(hit.try_add_delta(1, 0));
+
+        let axes: &[(isize, isize)] = match (horizontal, vertical) {
+            (true, _) => &[(0, -1), (0, 1)],
+            (_, true) => &[(-1, 0), (1, 0)],
+            _ => &[(0, -1), (0, 1), (-1, 0), (1, 0)],
+        };
+
+        let mut candidates = Vec::new();
+
+        // Walk over the known part of the ship and take the first unknown cell past its end
+        for &(dx, dy) in axes {
+            let mut point = hit;
+            while let Some(next) = point.try_add_delta(dx, dy) {
+                match sight.get(next) {
+                    Some(Knowledge::Hit) => point = next,
+                    Some(Knowledge::Unknown) => {
+                        candidates.push(next);
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+        }
+        candidates
+    }
+
+    fn hunt(sight: &Sight) -> Option<Point> {
+        // No ship fits between cells of the same parity spaced by its longest straight part
+        let spacing = sight
+            .remaining
+            .iter()
+            .map(Shape::longest_run)
+            .min()
+            .unwrap_or(1)
+            .max(1) as usize;
+
+        let checkerboard: Vec<Point> = sight
+            .with(Knowledge::Unknown)
+            .into_iter()
+            .filter(|point| (point.x as usize + point.y as usize).is_multiple_of(spacing))
+            .collect();
+
+        checkerboard
+            .choose(&mut rand::rng())
+            .copied()
+            .or_else(|| sight.random_unknown())
+    }
+}
+
+impl Strategy for HuntTarget {
+    fn target(&mut self, sight: &Sight) -> Option<Point> {
+        let hits = sight.with(Knowledge::Hit);
+        let mut candidates: Vec<Point> = hits
+            .iter()
+            .flat_map(|&hit| Self::follow_up(sight, hit))
+            .collect();
+
+        // Both ends of the line are closed, so the ship must turn a corner
+        if candidates.is_empty() {
+            candidates = hits
+                .iter()
+                .flat_map(|&hit| sight.neighbours(hit))
+                .filter(|&point| sight.get(point) == Some(Knowledge::Unknown))
+                .collect();
+        }
+
+        candidates
+            .choose(&mut rand::rng())
+            .copied()
+            .or_else(|| Self::hunt(sight))
+    }
+}
@@ -0,0 +1,164 @@
+mod density;
+mod hunt;
+mod random;
+
+use rand::seq::IndexedRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::{Board, Bounds, CellContent, HitDisplayDiff, Point, Shape},
+    utils::errors::WebResult,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Knowledge {
+    Unknown,
+    Miss,
+    Hit,
+    Sunk,
+}
+
+/// What a player can see of the opponent's board:
+/// shot results, sunk ships and the remaining ship counters.
+pub struct Sight {
+    bounds: Bounds,
+    /// Laid out the same way as the board cells
+    cells: Vec<Knowledge>,
+    // Shapes of all ships still afloat, one entry per ship
+    remaining: Vec<Shape>,
+    /// Ships may be next to each other, so wounded cells nearby can belong to another one
+    ships_touch: bool,
+}
+
+impl Sight {
+    fn of(board: &Board) -> Self {
+        let cells = board
+            .state
+            .iter()
+            .map(|cell| match (cell.content, cell.exposed) {
+                (_, false) => Knowledge::Unknown,
+                (CellContent::Ship(ship), true) => match board.ships[ship].has_sank() {
+                    true => Knowledge::Sunk,
+                    false => Knowledge::Hit,
+                },
+                (_, true) => Knowledge::Miss,
+            })
+            .collect();
+
+        let remaining = board
+            .ship_counters
+            .iter()
+            .flat_map(|counter| (0..counter.remaining).map(|_| counter.shape.clone()))
+            .collect();
+
+        Self {
+            bounds: board.bounds,
+            cells,
+            remaining,
+            ships_touch: board.ships_touch,
+        }
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        if point.x >= self.bounds.x || point.y >= self.bounds.y {
+            return None;
+        }
+        Some(point.x as usize * self.bounds.y as usize + point.y as usize)
+    }
+
+    fn get(&self, point: Point) -> Option<Knowledge> {
+        self.cells.get(self.index(point)?).copied()
+    }
+
+    fn points(&self) -> impl Iterator<Item = Point> + '_ {
+        let width = self.bounds.y as usize;
+        (0..self.cells.len()).map(move |i| Point::from_index(i / width, i % width))
+    }
+
+    fn with(&self, knowledge: Knowledge) -> Vec<Point> {
+        self.points()
+            .filter(|&point| self.get(point) == Some(knowledge))
+            .collect()
+    }
+
+    // Orthogonal neighbours that are still inside the board
+    fn neighbours(&self, point: Point) -> impl Iterator<Item = Point> + '_ {
+        [(-1, 0), (1, 0), (0, -1), (0, 1)]
+            .into_iter()
+            .filter_map(move |(dx, dy)| point.try_add_delta(dx, dy))
+            .filter(|&point| self.get(point).is_some())
+    }
+
+    fn random_unknown(&self) -> Option<Point> {
+        self.with(Knowledge::Unknown)
+            .choose(&mut rand::rng())
+            .copied()
+    }
+}
+
+/// A way of picking the next cell to shoot at.
+/// Strategies only get a [`Sight`], never the board itself.
+pub trait Strategy: Send + Sync {
+    // Returns None if there is nothing left to shoot at
+    fn target(&mut self, sight: &Sight) -> Option<Point>;
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    pub const ALL: [Self; 3] = [Self::Easy, Self::Medium, Self::Hard];
+
+    // Matches the serde representation used in query strings
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Easy => "easy",
+            Self::Medium => "medium",
+            Self::Hard => "hard",
+        }
+    }
+
+    fn strategy(self) -> Box<dyn Strategy> {
+        match self {
+            Self::Easy => Box::new(random::Random),
+            Self::Medium => Box::new(hunt::HuntTarget),
+            Self::Hard => Box::new(density::Density),
+        }
+    }
+}
+
+/// Computer player shooting back at the player's fleet
+pub struct Opponent {
+    difficulty: Difficulty,
+    strategy: Box<dyn Strategy>,
+}
+
+impl Opponent {
+    pub fn new(difficulty: Difficulty) -> Self {
+        Self {
+            difficulty,
+            strategy: difficulty.strategy(),
+        }
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        self.difficulty
+    }
+
+    // Returns None if there is nothing left to shoot at
+    pub fn shoot(&mut self, board: &mut Board) -> WebResult<Option<HitDisplayDiff>> {
+        let sight = Sight::of(board);
+
+        let Some(point) = self.strategy.target(&sight) else {
+            return Ok(None);
+        };
+
+        Ok(Some(board.hit(point)?))
+    }
+}
@@ -0,0 +1,13 @@
+use crate::game::{
+    Point,
+    ai::{Sight, Strategy},
+};
+
+/// Shoots at any cell it hasn't tried yet
+pub struct Random;
+
+impl Strategy for Random {
+    fn target(&mut self, sight: &Sight) -> Option<Point> {
+        sight.random_unknown()
+    }
+}
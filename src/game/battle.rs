@@ -0,0 +1,967 @@
+use std::ops::Range;
+
+use anyhow::{Result, anyhow, bail};
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use time::{Date, Duration, OffsetDateTime};
+
+use crate::{
+    game::{
+        Arsenal, Board, Fleet, HitDisplayDiff, MineEffect, Opponent, Point, Rules, Salvo, Seed,
+        Shot, Weapon,
+        snapshot::{ControlSnapshot, GameSnapshot, SideSnapshot},
+    },
+    utils::errors::{AnyhowWebExt, WebResult},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Seat {
+    /// Player who created the game
+    Host,
+    /// Player who joined it, or the computer
+    Guest,
+}
+
+impl Seat {
+    pub fn other(self) -> Self {
+        match self {
+            Self::Host => Self::Guest,
+            Self::Guest => Self::Host,
+        }
+    }
+
+    pub fn index(self) -> usize {
+        match self {
+            Self::Host => 0,
+            Self::Guest => 1,
+        }
+    }
+}
+
+enum Control {
+    Human,
+    Computer(Opponent),
+}
+
+struct Side {
+    fleet: Fleet,
+    control: Control,
+    /// Cells marked for the next salvo, not kept in snapshots
+    aim: Vec<Point>,
+    /// Special weapons left
+    ammo: Arsenal,
+    /// Weapon fired at the next cell clicked instead of a shot, not kept in snapshots
+    armed: Option<Weapon>,
+}
+
+pub struct Game {
+    sides: [Side; 2],
+    turn: Seat,
+    winner: Option<Seat>,
+    /// Seed of the computer's board, if it was generated
+    seed: Option<Seed>,
+    /// Day of the daily challenge this game is played for
+    daily: Option<Date>,
+    rules: Rules,
+    /// Turns each player still has to sit out for striking mines
+    skips: [usize; 2],
+    /// Every shot of both players, only taken back by an undo
+    log: Vec<Move>,
+    /// Moves can be undone, see [`Game::undo`]
+    practice: bool,
+    /// How the game was won, once it's over
+    ending: Ending,
+    /// Time of the first shot
+    started: Option<OffsetDateTime>,
+    finished: Option<OffsetDateTime>,
+}
+
+/// Way the game came to an end
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ending {
+    /// Every ship of the loser sank
+    #[default]
+    Sunk,
+    /// The loser fired all shots allowed by [`Rules::max_shots`]
+    OutOfShots,
+    /// The loser was to move when [`Rules::time_limit`] ran out
+    OutOfTime,
+    Surrender,
+}
+
+/// Entry of the move log
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Move {
+    pub(super) seat: Seat,
+    #[serde(flatten)]
+    pub(super) shot: Shot,
+    pub(super) at: OffsetDateTime,
+}
+
+pub struct Turn {
+    pub(super) player: HitDisplayDiff,
+    // The computer may shoot several times in a row.
+    // Cells of the player's ships opened by a mine they struck go here too.
+    pub(super) opponent: Vec<HitDisplayDiff>,
+    // Cells of the computer's ships opened by mines it struck
+    pub(super) revealed: Vec<HitDisplayDiff>,
+    // Whether the move went over to another human
+    pub passed: bool,
+    // The other human sits this turn out, so the move stays
+    pub(super) skipped: bool,
+    // Fired with the salvo button or a weapon, so no cell is replaced by the response
+    pub(super) detached: bool,
+    // Entries the move added at the end of the log
+    pub(super) logged: usize,
+}
+
+/// Moves taken back by [`Game::undo`]
+pub struct Undo {
+    // Cells of the opponent's board hidden again
+    pub(super) target: HitDisplayDiff,
+    // Cells of the player's fleet hidden again, when the computer's replies are taken back
+    pub(super) fleet: HitDisplayDiff,
+    // Positions of the removed entries in the log
+    pub(super) moves: Range<usize>,
+}
+
+/// Special weapons of a player, if the game has any
+pub struct Arms {
+    /// What every player got at the start
+    pub(super) total: Arsenal,
+    pub(super) ammo: Arsenal,
+    pub(super) armed: Option<Weapon>,
+}
+
+// What a player fires on their turn
+enum Attack<'a> {
+    Shots(&'a [Point]),
+    Weapon(Weapon, Point),
+}
+
+/// Cells marked for a salvo that hasn't been fired yet
+pub struct Aim<'a> {
+    pub(super) cells: &'a [Point],
+    /// Shots in the salvo
+    pub(super) shots: usize,
+}
+
+pub enum Outcome {
+    Ongoing(Turn),
+    Win,
+    Loss,
+}
+
+impl Outcome {
+    fn of(seat: Seat, winner: Seat) -> Self {
+        match seat == winner {
+            true => Self::Win,
+            false => Self::Loss,
+        }
+    }
+}
+
+impl Game {
+    /// Player against the computer, which has its fleet placed already
+    pub fn solo(fleet: Fleet, board: Board, opponent: Opponent) -> Self {
+        Self {
+            sides: [
+                Side {
+                    fleet,
+                    control: Control::Human,
+                    aim: Vec::new(),
+                    ammo: Arsenal::default(),
+                    armed: None,
+                },
+                Side {
+                    fleet: Fleet::Ready(board),
+                    control: Control::Computer(opponent),
+                    aim: Vec::new(),
+                    ammo: Arsenal::default(),
+                    armed: None,
+                },
+            ],
+            turn: Seat::Host,
+            winner: None,
+            seed: None,
+            daily: None,
+            rules: Rules::default(),
+            skips: [0; 2],
+            log: Vec::new(),
+            practice: false,
+            ending: Ending::default(),
+            started: None,
+            finished: None,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: Seed) -> Self {
+        self.seed.replace(seed);
+        self
+    }
+
+    pub fn with_daily(mut self, date: Date) -> Self {
+        self.daily.replace(date);
+        self
+    }
+
+    /// Lets the player undo moves.
+    /// Meant for the classic rules, mine effects and weapon uses aren't taken back.
+    pub fn with_practice(mut self) -> Self {
+        self.practice = true;
+        self
+    }
+
+    pub fn is_practice(&self) -> bool {
+        self.practice
+    }
+
+    pub fn with_rules(mut self, rules: Rules) -> Self {
+        self.rules = rules;
+        for side in &mut self.sides {
+            side.ammo = rules.arsenal;
+        }
+        self
+    }
+
+    /// Two players, each placing their own fleet
+    pub fn online(host: Fleet, guest: Fleet) -> Self {
+        Self {
+            sides: [
+                Side {
+                    fleet: host,
+                    control: Control::Human,
+                    aim: Vec::new(),
+                    ammo: Arsenal::default(),
+                    armed: None,
+                },
+                Side {
+                    fleet: guest,
+                    control: Control::Human,
+                    aim: Vec::new(),
+                    ammo: Arsenal::default(),
+                    armed: None,
+                },
+            ],
+            turn: Seat::Host,
+            winner: None,
+            seed: None,
+            daily: None,
+            rules: Rules::default(),
+            skips: [0; 2],
+            log: Vec::new(),
+            practice: false,
+            ending: Ending::default(),
+            started: None,
+            finished: None,
+        }
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.sides
+            .iter()
+            .all(|side| matches!(side.control, Control::Human))
+    }
+
+    fn side(&self, seat: Seat) -> &Side {
+        &self.sides[seat.index()]
+    }
+
+    // Returns the side of `seat` and the side of its opponent
+    fn split(sides: &mut [Side; 2], seat: Seat) -> (&mut Side, &mut Side) {
+        let [host, guest] = sides;
+        match seat {
+            Seat::Host => (host, guest),
+            Seat::Guest => (guest, host),
+        }
+    }
+
+    pub fn fleet(&self, seat: Seat) -> &Fleet {
+        &self.side(seat).fleet
+    }
+
+    pub fn fleet_mut(&mut self, seat: Seat) -> &mut Fleet {
+        &mut self.sides[seat.index()].fleet
+    }
+
+    pub fn is_ready(&self, seat: Seat) -> bool {
+        matches!(self.fleet(seat), Fleet::Ready(_))
+    }
+
+    pub fn turn(&self) -> Seat {
+        self.turn
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    pub fn seed(&self) -> Option<Seed> {
+        self.seed
+    }
+
+    pub fn daily(&self) -> Option<Date> {
+        self.daily
+    }
+
+    /// Number of shots `seat` has fired at the opponent
+    pub fn shots(&self, seat: Seat) -> usize {
+        match self.fleet(seat.other()) {
+            Fleet::Ready(board) => board.shots.len(),
+            Fleet::Placing(_) => 0,
+        }
+    }
+
+    pub fn result(&self, seat: Seat) -> Option<Outcome> {
+        self.winner.map(|winner| Outcome::of(seat, winner))
+    }
+
+    pub fn ending(&self) -> Ending {
+        self.ending
+    }
+
+    fn finish(&mut self, winner: Seat, ending: Ending) {
+        self.winner = Some(winner);
+        self.ending = ending;
+        self.finished = Some(OffsetDateTime::now_utc());
+    }
+
+    pub fn finished(&self) -> Option<OffsetDateTime> {
+        self.finished
+    }
+
+    /// Time from the first shot to the end of the game, or until now if it goes on
+    pub fn duration(&self) -> Duration {
+        let Some(started) = self.started else {
+            return Duration::ZERO;
+        };
+        self.finished.unwrap_or_else(OffsetDateTime::now_utc) - started
+    }
+
+    /// Time left to play, if the game has a time limit
+    pub fn time_left(&self) -> Option<Duration> {
+        let limit = Duration::minutes(self.rules.time_limit?.into());
+        Some(limit - self.duration())
+    }
+
+    /// Ends the game if its time is up
+    pub fn check_time(&mut self) {
+        if self.winner.is_none() && self.time_left().is_some_and(|left| !left.is_positive()) {
+            self.finish(self.turn.other(), Ending::OutOfTime);
+        }
+    }
+
+    /// Shots `seat` may still fire, if they are limited
+    pub fn shots_left(&self, seat: Seat) -> Option<usize> {
+        let max = usize::from(self.rules.max_shots?);
+        Some(max.saturating_sub(self.shots(seat)))
+    }
+
+    fn is_out_of_shots(rules: Rules, target: &Board) -> bool {
+        rules
+            .max_shots
+            .is_some_and(|max| target.shots.len() >= max.into())
+    }
+
+    /// Gives the game up, the opponent wins right away
+    pub fn surrender(&mut self, seat: Seat) -> Outcome {
+        if let Some(outcome) = self.result(seat) {
+            return outcome;
+        }
+
+        self.finish(seat.other(), Ending::Surrender);
+        Outcome::Loss
+    }
+
+    // Shots fired per turn by the owner of `own` at `target`
+    fn salvo_size(rules: Rules, own: &Board, target: &Board) -> usize {
+        let shots = match rules.salvo {
+            Salvo::Off => 1,
+            Salvo::Fixed(shots) => shots.max(1).into(),
+            Salvo::PerShip => own.ships_afloat(),
+        };
+
+        // The last salvo only has the shots left under the limit
+        let allowed = rules.max_shots.map_or(usize::MAX, |max| {
+            usize::from(max).saturating_sub(target.shots.len())
+        });
+
+        // Near the end there may be fewer cells left than shots
+        shots.min(allowed).min(target.hidden_cells().len())
+    }
+
+    /// Salvo being aimed by `seat`, if salvos are played and the battle is on
+    pub fn aim(&self, seat: Seat) -> Option<Aim<'_>> {
+        if self.rules.salvo == Salvo::Off {
+            return None;
+        }
+
+        let (Fleet::Ready(own), Fleet::Ready(target)) =
+            (self.fleet(seat), self.fleet(seat.other()))
+        else {
+            return None;
+        };
+
+        Some(Aim {
+            cells: &self.side(seat).aim,
+            shots: Self::salvo_size(self.rules, own, target),
+        })
+    }
+
+    fn check_turn(&self, seat: Seat) -> WebResult<()> {
+        if self.turn != seat {
+            return Err(anyhow!("Not your turn")
+                .client_error()
+                .code(StatusCode::CONFLICT));
+        }
+        Ok(())
+    }
+
+    // Boards of both players, once they are placed
+    fn boards<'a>(
+        own: &'a mut Fleet,
+        enemy: &'a mut Fleet,
+    ) -> WebResult<(&'a mut Board, &'a mut Board)> {
+        let Fleet::Ready(fleet) = own else {
+            return Err(anyhow!("Fleet is not placed yet")
+                .client_error()
+                .code(StatusCode::CONFLICT));
+        };
+
+        let Fleet::Ready(target) = enemy else {
+            return Err(anyhow!("Opponent is not ready yet")
+                .client_error()
+                .code(StatusCode::CONFLICT));
+        };
+
+        Ok((fleet, target))
+    }
+
+    /// Marks a cell for the next salvo, or unmarks it if it was marked already
+    pub fn toggle_aim(&mut self, seat: Seat, point: Point) -> WebResult<()> {
+        let Some(shots) = self.aim(seat).map(|aim| aim.shots) else {
+            return Err(anyhow!("Salvos are not played in this game")
+                .client_error()
+                .code(StatusCode::CONFLICT));
+        };
+
+        self.check_turn(seat)?;
+
+        if let Fleet::Ready(target) = self.fleet(seat.other())
+            && target.get_cell(point).is_none_or(|cell| cell.exposed)
+        {
+            return Err(anyhow!("Cell {point} can't be shot at")
+                .client_error()
+                .code(StatusCode::CONFLICT));
+        }
+
+        let aim = &mut self.sides[seat.index()].aim;
+
+        match aim.iter().position(|&aimed| aimed == point) {
+            Some(i) => {
+                aim.remove(i);
+            }
+            None if aim.len() < shots => aim.push(point),
+            None => {
+                return Err(anyhow!("All shots of the salvo are aimed already")
+                    .client_error()
+                    .code(StatusCode::CONFLICT));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn fire(&mut self, seat: Seat, point: Point) -> WebResult<Outcome> {
+        if self.rules.salvo != Salvo::Off {
+            return Err(anyhow!("Shots are fired in salvos in this game")
+                .client_error()
+                .code(StatusCode::CONFLICT));
+        }
+
+        self.volley(seat, Attack::Shots(&[point]))
+    }
+
+    /// Special weapons of `seat`, if the game has any and the battle is on
+    pub fn arms(&self, seat: Seat) -> Option<Arms> {
+        if self.rules.arsenal.is_empty() || !self.is_ready(seat) || !self.is_ready(seat.other()) {
+            return None;
+        }
+
+        let side = self.side(seat);
+        Some(Arms {
+            total: self.rules.arsenal,
+            ammo: side.ammo,
+            armed: side.armed,
+        })
+    }
+
+    pub fn armed(&self, seat: Seat) -> Option<Weapon> {
+        self.side(seat).armed
+    }
+
+    /// Readies a weapon for the next cell clicked, or puts it away if it was ready already
+    pub fn arm(&mut self, seat: Seat, weapon: Weapon) -> WebResult<()> {
+        let Some(arms) = self.arms(seat) else {
+            return Err(anyhow!("Special weapons are not played in this game")
+                .client_error()
+                .code(StatusCode::CONFLICT));
+        };
+
+        if arms.ammo.get(weapon) == 0 {
+            return Err(anyhow!("No {} left", weapon.id())
+                .client_error()
+                .code(StatusCode::CONFLICT));
+        }
+
+        let side = &mut self.sides[seat.index()];
+        side.armed = match side.armed {
+            Some(armed) if armed == weapon => None,
+            _ => Some(weapon),
+        };
+        Ok(())
+    }
+
+    /// Fires the weapon readied with [`Game::arm`] at the cell, instead of a shot or a salvo
+    pub fn fire_weapon(&mut self, seat: Seat, point: Point) -> WebResult<Outcome> {
+        let Some(weapon) = self.armed(seat) else {
+            return Err(anyhow!("No weapon is ready")
+                .client_error()
+                .code(StatusCode::CONFLICT));
+        };
+
+        let outcome = self.volley(seat, Attack::Weapon(weapon, point))?;
+
+        // Cells marked for a salvo stay marked, unless the weapon opened them
+        let (own, enemy) = Self::split(&mut self.sides, seat);
+        own.armed = None;
+        if let Fleet::Ready(target) = &enemy.fleet {
+            own.aim
+                .retain(|&aimed| target.get_cell(aimed).is_some_and(|cell| !cell.exposed));
+        }
+        Ok(outcome)
+    }
+
+    /// Fires every cell marked with [`Game::toggle_aim`]
+    pub fn fire_salvo(&mut self, seat: Seat) -> WebResult<Outcome> {
+        let Some(aim) = self.aim(seat) else {
+            return Err(anyhow!("Salvos are not played in this game")
+                .client_error()
+                .code(StatusCode::CONFLICT));
+        };
+
+        if aim.cells.len() != aim.shots {
+            return Err(anyhow!("The salvo needs {} shots", aim.shots)
+                .client_error()
+                .code(StatusCode::CONFLICT));
+        }
+
+        let points = aim.cells.to_vec();
+        let outcome = self.volley(seat, Attack::Shots(&points))?;
+
+        self.sides[seat.index()].aim.clear();
+        Ok(outcome)
+    }
+
+    // Adds the shots of a move to the log
+    fn record(log: &mut Vec<Move>, seat: Seat, diff: &HitDisplayDiff) {
+        let at = OffsetDateTime::now_utc();
+
+        log.extend(diff.fired.iter().map(|shot| Move {
+            seat,
+            shot: shot.clone(),
+            at,
+        }));
+    }
+
+    /// Takes back the player's last move, along with the computer's replies to it
+    pub fn undo(&mut self, seat: Seat) -> WebResult<Undo> {
+        // Ranked games count every shot
+        if !self.practice || self.daily.is_some() || self.is_online() {
+            return Err(anyhow!("Moves can only be undone in practice games")
+                .client_error()
+                .code(StatusCode::FORBIDDEN));
+        }
+
+        if self.winner.is_some() {
+            return Err(anyhow!("The game is over")
+                .client_error()
+                .code(StatusCode::CONFLICT));
+        }
+
+        let Some(start) = self.log.iter().rposition(|entry| entry.seat == seat) else {
+            return Err(anyhow!("There are no moves to undo")
+                .client_error()
+                .code(StatusCode::CONFLICT));
+        };
+
+        let mut undo = Undo {
+            target: HitDisplayDiff::default(),
+            fleet: HitDisplayDiff::default(),
+            moves: start..self.log.len(),
+        };
+
+        let (own, enemy) = Self::split(&mut self.sides, seat);
+        let (fleet, target) = Self::boards(&mut own.fleet, &mut enemy.fleet)?;
+
+        // Works on copies, so a failure halfway leaves the boards in step with the log
+        let (mut fleet_after, mut target_after) = (fleet.clone(), target.clone());
+
+        // Latest first, each board only takes back its last shot
+        for entry in self.log[start..].iter().rev() {
+            match entry.seat == seat {
+                true => undo.target.merge(target_after.unhit(entry.shot.point)?),
+                false => undo.fleet.merge(fleet_after.unhit(entry.shot.point)?),
+            }
+        }
+
+        *fleet = fleet_after;
+        *target = target_after;
+        self.log.truncate(start);
+        self.turn = seat;
+        Ok(undo)
+    }
+
+    /// Every shot of the game so far, in order
+    pub fn log(&self) -> &[Move] {
+        &self.log
+    }
+
+    fn volley(&mut self, seat: Seat, attack: Attack) -> WebResult<Outcome> {
+        self.check_time();
+        if let Some(outcome) = self.result(seat) {
+            return Ok(outcome);
+        }
+
+        self.check_turn(seat)?;
+        self.started.get_or_insert_with(OffsetDateTime::now_utc);
+
+        let rules = self.rules;
+        let logged = self.log.len();
+        let (own, enemy) = Self::split(&mut self.sides, seat);
+        let (fleet, target) = Self::boards(&mut own.fleet, &mut enemy.fleet)?;
+
+        let player = match attack {
+            Attack::Shots(points) => target.hit_many(points)?,
+            Attack::Weapon(weapon, point) => {
+                if own.ammo.get(weapon) == 0 {
+                    return Err(anyhow!("No {} left", weapon.id())
+                        .client_error()
+                        .code(StatusCode::CONFLICT));
+                }
+
+                let diff = target.strike(weapon, point)?;
+                own.ammo.take(weapon);
+                diff
+            }
+        };
+        let detached = !matches!(attack, Attack::Shots(_)) || rules.salvo != Salvo::Off;
+        Self::record(&mut self.log, seat, &player);
+
+        if target.is_win() {
+            self.finish(seat, Ending::Sunk);
+            return Ok(Outcome::Win);
+        }
+
+        if Self::is_out_of_shots(rules, target) {
+            self.finish(seat.other(), Ending::OutOfShots);
+            return Ok(Outcome::Loss);
+        }
+
+        let mut opponent = Vec::new();
+        let mut revealed = Vec::new();
+
+        match rules.mine_effect {
+            MineEffect::SkipTurn => self.skips[seat.index()] += player.mines,
+            MineEffect::Reveal => {
+                opponent.extend(fleet.reveal(player.mines));
+                if fleet.is_win() {
+                    self.finish(seat.other(), Ending::Sunk);
+                    return Ok(Outcome::Loss);
+                }
+            }
+        }
+
+        let salvo = rules.salvo != Salvo::Off;
+        let repeat = rules.repeat_on_hit && !salvo;
+
+        if repeat && player.is_hit() {
+            return Ok(Outcome::Ongoing(Turn {
+                player,
+                opponent,
+                revealed,
+                passed: false,
+                skipped: false,
+                detached,
+                logged: self.log.len() - logged,
+            }));
+        }
+
+        // The computer replies right away, a human has to wait for their turn
+        let (passed, skipped) = match &mut enemy.control {
+            // The opponent struck a mine earlier and sits this turn out
+            control if self.skips[seat.other().index()] > 0 => {
+                self.skips[seat.other().index()] -= 1;
+                (false, matches!(control, Control::Human))
+            }
+            Control::Computer(computer) => {
+                loop {
+                    let mut hit = false;
+                    let mut mines = 0;
+
+                    for _ in 0..Self::salvo_size(rules, target, fleet) {
+                        let Some(diff) = computer.shoot(fleet)? else {
+                            break;
+                        };
+                        Self::record(&mut self.log, seat.other(), &diff);
+                        hit |= diff.is_hit();
+                        mines += diff.mines;
+                        opponent.push(diff);
+
+                        if fleet.is_win() {
+                            self.finish(seat.other(), Ending::Sunk);
+                            return Ok(Outcome::Loss);
+                        }
+
+                        if Self::is_out_of_shots(rules, fleet) {
+                            self.finish(seat, Ending::OutOfShots);
+                            return Ok(Outcome::Win);
+                        }
+                    }
+
+                    match rules.mine_effect {
+                        MineEffect::SkipTurn => self.skips[seat.other().index()] += mines,
+                        MineEffect::Reveal => {
+                            revealed.extend(target.reveal(mines));
+                            if target.is_win() {
+                                self.finish(seat, Ending::Sunk);
+                                return Ok(Outcome::Win);
+                            }
+                        }
+                    }
+
+                    if repeat && hit {
+                        continue;
+                    }
+
+                    // The player sits this turn out, so the computer goes again
+                    if self.skips[seat.index()] > 0 {
+                        self.skips[seat.index()] -= 1;
+                        continue;
+                    }
+                    break;
+                }
+                (false, false)
+            }
+            Control::Human => {
+                self.turn = seat.other();
+                (true, false)
+            }
+        };
+
+        Ok(Outcome::Ongoing(Turn {
+            player,
+            opponent,
+            revealed,
+            passed,
+            skipped,
+            detached,
+            logged: self.log.len() - logged,
+        }))
+    }
+
+    pub fn snapshot(&self) -> GameSnapshot {
+        let [host, guest] = &self.sides;
+
+        GameSnapshot {
+            sides: [host.snapshot(), guest.snapshot()],
+            turn: self.turn,
+            winner: self.winner,
+            seed: self.seed,
+            daily: self.daily,
+            rules: self.rules,
+            skips: self.skips,
+            log: self.log.clone(),
+            practice: self.practice,
+            ending: self.ending,
+            started: self.started,
+            finished: self.finished,
+        }
+    }
+
+    pub fn restore(snapshot: &GameSnapshot) -> Result<Self> {
+        let [host, guest] = &snapshot.sides;
+        let sides = [Side::restore(host)?, Side::restore(guest)?];
+
+        // Undo walks the log back over the shots on the boards, so the two have to agree.
+        // Snapshots from before the log was kept have none at all.
+        if !snapshot.log.is_empty() {
+            for seat in [Seat::Host, Seat::Guest] {
+                let shots = match &sides[seat.other().index()].fleet {
+                    Fleet::Ready(board) => board.shots.as_slice(),
+                    Fleet::Placing(_) => &[],
+                };
+                let logged = snapshot
+                    .log
+                    .iter()
+                    .filter(|entry| entry.seat == seat)
+                    .map(|entry| entry.shot.point);
+
+                if !logged.eq(shots.iter().copied()) {
+                    bail!("The log doesn't match the shots on the boards");
+                }
+            }
+        }
+
+        Ok(Self {
+            sides,
+            turn: snapshot.turn,
+            winner: snapshot.winner,
+            seed: snapshot.seed,
+            daily: snapshot.daily,
+            rules: snapshot.rules,
+            skips: snapshot.skips,
+            log: snapshot.log.clone(),
+            // Undo doesn't take back mines and weapons, see `with_practice`
+            practice: snapshot.practice && snapshot.rules.is_classic(),
+            ending: snapshot.ending,
+            started: snapshot.started,
+            finished: snapshot.finished,
+        })
+    }
+}
+
+impl Side {
+    fn snapshot(&self) -> SideSnapshot {
+        SideSnapshot {
+            fleet: self.fleet.snapshot(),
+            control: match &self.control {
+                Control::Human => ControlSnapshot::Human,
+                Control::Computer(opponent) => ControlSnapshot::Computer(opponent.difficulty()),
+            },
+            ammo: self.ammo,
+        }
+    }
+
+    fn restore(snapshot: &SideSnapshot) -> Result<Self> {
+        Ok(Self {
+            fleet: Fleet::restore(&snapshot.fleet)?,
+            control: match snapshot.control {
+                ControlSnapshot::Human => Control::Human,
+                ControlSnapshot::Computer(difficulty) => {
+                    Control::Computer(Opponent::new(difficulty))
+                }
+            },
+            aim: Vec::new(),
+            ammo: snapshot.ammo,
+            armed: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{BoardBuilder, Difficulty, ShipDefinition, seeded_rng};
+
+    fn board(seed: Seed) -> Board {
+        BoardBuilder::square(10)
+            .random(&ShipDefinition::classic(), &mut seeded_rng(seed))
+            .expect("classic fleet fits")
+    }
+
+    fn practice() -> Game {
+        Game::solo(
+            Fleet::Ready(board(1)),
+            board(2),
+            Opponent::new(Difficulty::default()),
+        )
+        .with_practice()
+    }
+
+    fn json(game: &Game) -> String {
+        serde_json::to_string(&game.snapshot()).unwrap()
+    }
+
+    #[test]
+    fn undo_restores_the_game() {
+        let mut game = practice();
+        // The first shot starts the clock, which undo leaves running
+        game.fire(Seat::Host, Point::new(0, 0)).unwrap();
+        let before = json(&game);
+
+        for i in 1..6 {
+            game.fire(Seat::Host, Point::new(i, i)).unwrap();
+        }
+        assert!(game.log().len() >= 12);
+
+        for _ in 1..6 {
+            game.undo(Seat::Host).unwrap();
+        }
+        assert_eq!(json(&game), before);
+    }
+
+    #[test]
+    fn salvo_modes_set_the_volley() {
+        let size = |salvo, own: &Board, target: &Board| {
+            let rules = Rules {
+                salvo,
+                ..Rules::default()
+            };
+            Game::salvo_size(rules, own, target)
+        };
+        let (mut own, target) = (board(1), board(2));
+
+        assert_eq!(size(Salvo::Off, &own, &target), 1);
+        assert_eq!(size(Salvo::Fixed(0), &own, &target), 1);
+        assert_eq!(size(Salvo::Fixed(3), &own, &target), 3);
+        assert_eq!(size(Salvo::PerShip, &own, &target), 10);
+
+        // Sinking a torpedo boat takes a shot away
+        let torpedo = own.ships.iter().find(|ship| ship.kind == 3).unwrap();
+        let cell = torpedo.cells[0];
+        own.hit(cell).unwrap();
+        assert_eq!(size(Salvo::PerShip, &own, &target), 9);
+
+        // There are never more shots than cells left to fire at
+        let tiny = BoardBuilder::square(2)
+            .random(&[ShipDefinition::new("Торпеда", 1, 1)], &mut seeded_rng(3))
+            .unwrap();
+        assert_eq!(size(Salvo::Fixed(5), &own, &tiny), 4);
+    }
+
+    #[test]
+    fn last_salvo_keeps_to_the_shot_limit() {
+        let mut game = practice().with_rules(Rules {
+            salvo: Salvo::Fixed(3),
+            max_shots: Some(4),
+            ..Rules::default()
+        });
+        assert_eq!(game.aim(Seat::Host).unwrap().shots, 3);
+
+        for i in 0..3 {
+            game.toggle_aim(Seat::Host, Point::new(0, i)).unwrap();
+        }
+        game.fire_salvo(Seat::Host).unwrap();
+        assert_eq!(game.aim(Seat::Host).unwrap().shots, 1);
+        assert_eq!(game.shots_left(Seat::Host), Some(1));
+    }
+
+    #[test]
+    fn restore_checks_the_log() {
+        let mut game = practice();
+        for i in 0..3 {
+            game.fire(Seat::Host, Point::new(i, 9 - i)).unwrap();
+        }
+
+        let mut snapshot = game.snapshot();
+        let restored = Game::restore(&snapshot).unwrap();
+        assert_eq!(json(&restored), json(&game));
+
+        snapshot.log.swap(0, 2);
+        assert!(Game::restore(&snapshot).is_err());
+
+        snapshot.log.truncate(1);
+        assert!(Game::restore(&snapshot).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use rand::Rng;
+
+use crate::game::{Board, BoardBuilder, FitError, ShipDefinition};
+
+/// Longest side of a board we agree to generate
+pub const MAX_SIDE: u8 = 50;
+
+/// Why a board size and fleet can't make a game
+pub enum FleetError {
+    EmptyBoard,
+    BoardTooLarge,
+    EmptyFleet,
+    UnnamedShip,
+    DuplicateName {
+        ship: ShipDefinition,
+    },
+    ZeroLength {
+        ship: ShipDefinition,
+    },
+    TooLong {
+        ship: ShipDefinition,
+    },
+    /// Cells of the shape don't make a single piece
+    BadShape {
+        ship: ShipDefinition,
+    },
+    /// Ships cover more cells than any fleet could on this board
+    TooDense {
+        cells: usize,
+        max_cells: usize,
+    },
+    NoRoom(FitError),
+}
+
+impl From<FitError> for FleetError {
+    fn from(value: FitError) -> Self {
+        Self::NoRoom(value)
+    }
+}
+
+/// How much of the board a fleet that does fit takes up
+pub struct FleetReport {
+    pub ships: usize,
+    /// Cells covered by ships
+    pub cells: usize,
+    /// Share of the board covered by ships
+    pub density: f32,
+    /// Highest share any fleet could cover on this board
+    pub max_density: f32,
+}
+
+impl BoardBuilder {
+    /// Checks that `ships` can be placed on the board under its touching rule,
+    /// around whatever islands and mines it has.
+    /// A layout is actually searched for, so a passing fleet is known to fit,
+    /// but a failing one is only known not to when the error is [`FitError::NoRoom`].
+    /// The search is capped and takes its choices from `rng`,
+    /// so the same rng state always gives the same answer.
+    pub fn validate(
+        &self,
+        ships: &[ShipDefinition],
+        rng: &mut impl Rng,
+    ) -> Result<FleetReport, FleetError> {
+        self.random_checked(ships, rng).map(|(report, _)| report)
+    }
+
+    /// Same as `validate`, but hands over the board the search found
+    pub fn random_checked(
+        &self,
+        ships: &[ShipDefinition],
+        rng: &mut impl Rng,
+    ) -> Result<(FleetReport, Board), FleetError> {
+        let ships: Vec<ShipDefinition> = ships
+            .iter()
+            .filter(|ship| ship.count > 0)
+            .cloned()
+            .collect();
+
+        let report = self.check(&ships)?;
+        let board = self.bare().random(&ships, rng)?;
+        Ok((report, board))
+    }
+
+    // Everything `validate` can rule out without searching for a layout,
+    // `ships` only has the ones the fleet actually has
+    fn check(&self, ships: &[ShipDefinition]) -> Result<FleetReport, FleetError> {
+        let (rows, columns) = (self.bounds.x, self.bounds.y);
+
+        if rows == 0 || columns == 0 {
+            return Err(FleetError::EmptyBoard);
+        }
+        if rows > MAX_SIDE || columns > MAX_SIDE {
+            return Err(FleetError::BoardTooLarge);
+        }
+
+        if ships.is_empty() {
+            return Err(FleetError::EmptyFleet);
+        }
+
+        for (i, ship) in ships.iter().enumerate() {
+            if ship.name.trim().is_empty() {
+                return Err(FleetError::UnnamedShip);
+            }
+            if ships[..i].iter().any(|other| other.name == ship.name) {
+                return Err(FleetError::DuplicateName { ship: ship.clone() });
+            }
+            if ship
+                .shape
+                .as_ref()
+                .is_some_and(|shape| !shape.is_connected())
+            {
+                return Err(FleetError::BadShape { ship: ship.clone() });
+            }
+            if ship.length == 0 {
+                return Err(FleetError::ZeroLength { ship: ship.clone() });
+            }
+            if !ship.shape().fits(rows, columns) {
+                return Err(FleetError::TooLong { ship: ship.clone() });
+            }
+        }
+
+        let area = rows as usize * columns as usize;
+        let cells: usize = ships
+            .iter()
+            .map(|ship| ship.length as usize * ship.count as usize)
+            .sum();
+
+        // Best case is full-length ships on every other line, or on all of them if they may touch
+        let max_cells = match self.inner.ships_touch {
+            true => area,
+            false => (rows.div_ceil(2) as usize * columns as usize)
+                .max(rows as usize * columns.div_ceil(2) as usize),
+        };
+
+        if cells > max_cells {
+            return Err(FleetError::TooDense { cells, max_cells });
+        }
+
+        Ok(FleetReport {
+            ships: ships.iter().map(|ship| ship.count as usize).sum(),
+            cells,
+            density: cells as f32 / area as f32,
+            max_density: max_cells as f32 / area as f32,
+        })
+    }
+}
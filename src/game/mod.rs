@@ -1,22 +1,42 @@
-#![allow(dead_code)] // TODO
+mod ai;
+mod battle;
+mod feasibility;
+mod placement;
+mod replay;
+mod rules;
+mod shape;
+mod snapshot;
+mod stats;
 pub mod ui;
+mod weapons;
+
+pub use ai::{Difficulty, Opponent};
+pub use battle::{Game, Outcome, Seat};
+pub use feasibility::{FleetError, FleetReport, MAX_SIDE};
+pub use placement::{Fleet, Placement};
+pub use replay::Replay;
+pub use rules::{Boards, MineEffect, Rules, Salvo, Setup};
+pub use shape::Shape;
+pub use snapshot::GameSnapshot;
+pub use stats::Summary;
+pub use ui::Alphabet;
+pub use weapons::{Arsenal, Weapon};
 
 use anyhow::{Context, Result, anyhow, bail};
 use axum::http::StatusCode;
-use rand::Rng;
+use rand::{
+    Rng, SeedableRng,
+    rngs::StdRng,
+    seq::{IndexedRandom, SliceRandom},
+};
+use serde::{Deserialize, Serialize};
 use shrinkwraprs::Shrinkwrap;
-use tokio::sync::RwLock;
 
-use std::{
-    collections::HashSet, fmt::Display, hash::Hash, ops::SubAssign, str::FromStr, sync::Arc,
-};
+use std::{cmp::Reverse, fmt::Display, hash::Hash, ops::SubAssign, str::FromStr, sync::Arc};
 
 use crate::utils::errors::{AnyhowWebExt, WebResult};
 
-// TODO: how did we get here...
-type Dyn<T> = Arc<RwLock<T>>;
-
-#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
 pub struct Point {
     x: u8,
     y: u8,
@@ -62,37 +82,64 @@ impl FromStr for Point {
 
 type Bounds = Point; // Bounds are just the maximum point in both coordinates
 
+impl Point {
+    /// Parses a cell id sent back by the page, which must lie within `bounds`
+    pub fn parse_within(s: &str, bounds: Bounds) -> Result<Self> {
+        let point: Self = s.parse()?;
+
+        if point.x >= bounds.x || point.y >= bounds.y {
+            bail!("cell {point} is outside of the board");
+        }
+        Ok(point)
+    }
+}
+
+/// Reproduces a randomly generated board, see [`BoardBuilder::random`]
+pub type Seed = u64;
+
+pub fn seeded_rng(seed: Seed) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+#[derive(Clone, Copy)]
 enum CellContent {
     Water,
-    NearShip(Dyn<Ship>),
-    Ship(Dyn<Ship>),
+    /// Index of the ship in [`Board::ships`]
+    NearShip(usize),
+    Ship(usize),
+    /// Hidden until shot at, see [`MineEffect`]
+    Mine,
+    /// Land, open to both players from the start
+    Island,
 }
 
 impl CellContent {
     fn contains_ship(&self) -> bool {
-        match self {
-            Self::Ship(_) => true,
-            _ => false,
-        }
+        matches!(self, Self::Ship(_))
+    }
+
+    // Placed before the ships, which have to go around it
+    fn is_terrain(&self) -> bool {
+        matches!(self, Self::Mine | Self::Island)
     }
 
-    fn get_ship(&mut self) -> Option<Dyn<Ship>> {
+    fn get_ship(&self) -> Option<usize> {
         match self {
-            Self::Ship(ship) => Some(ship.clone()),
+            Self::Ship(ship) => Some(*ship),
             _ => None,
         }
     }
 
-    fn get_collision(&self) -> Option<Dyn<Ship>> {
+    fn get_collision(&self) -> Option<usize> {
         match self {
-            Self::Ship(ship) => Some(ship.clone()),
-            Self::NearShip(ship) => Some(ship.clone()),
+            Self::Ship(ship) => Some(*ship),
+            Self::NearShip(ship) => Some(*ship),
             _ => None,
         }
     }
 }
 
-#[derive(Shrinkwrap)]
+#[derive(Clone, Copy, Shrinkwrap)]
 #[shrinkwrap(mutable)]
 struct CellState {
     #[shrinkwrap(main_field)]
@@ -105,6 +152,11 @@ impl CellState {
     fn expose(&mut self) {
         self.exposed = true;
     }
+
+    #[inline]
+    fn conceal(&mut self) {
+        self.exposed = false;
+    }
 }
 
 impl Default for CellState {
@@ -116,77 +168,104 @@ impl Default for CellState {
     }
 }
 
-#[derive(Clone, Shrinkwrap)]
-struct CellRef {
-    #[shrinkwrap(main_field)]
-    accessor: Dyn<CellState>,
-    point: Point,
+/// What a move did to the opponent's fleet, as told to the player
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShotResult {
+    Miss,
+    Hit,
+    Sunk,
 }
 
-impl CellRef {
-    // Returns a ship if one was hit
-    async fn hit(&self) -> Result<Option<Dyn<Ship>>> {
-        let mut cell = self.accessor.write().await;
-
-        if cell.exposed {
-            bail!("Cell already hit")
-        } else {
-            cell.expose();
-        };
-
-        let ship = match cell.get_ship() {
-            None => return Ok(None),
-            Some(ship) => ship,
-        };
-
-        ship.write().await.hit().await;
-
-        Ok(Some(ship))
-    }
+/// A single cell shot at, as it goes into the move log
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Shot {
+    point: Point,
+    result: ShotResult,
+    /// Name of the ship the shot sank
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sunk: Option<String>,
 }
 
+/// Cells changed by a shot or a salvo, copied out so they can be drawn without the board
+#[derive(Default)]
 pub struct HitDisplayDiff {
-    cell: CellRef,
-    sank_ship: Option<Dyn<Ship>>,
+    // The cell that was shot first comes first
+    cells: Vec<(Point, CellState)>,
+    /// Counters of the ships that were sunk, as they were right after sinking.
+    /// An undo puts the counters of the ships it raises here.
+    sank_ships: Vec<ShipCounter>,
+    /// Every cell of the sunk ships, drawn with their outline
+    outlines: Vec<(Point, Outline)>,
+    /// Shots that struck a mine
+    mines: usize,
+    /// Hidden cells a sonar ping went over, with what is known about them now
+    pinged: Vec<(Point, bool)>,
+    /// Every cell shot at, in order
+    fired: Vec<Shot>,
 }
 
 impl HitDisplayDiff {
-    fn single_cell(cell: CellRef) -> Self {
-        Self {
-            cell,
-            sank_ship: None,
-        }
+    pub fn is_hit(&self) -> bool {
+        self.cells.iter().any(|(_, cell)| cell.contains_ship())
     }
 
-    fn sank_ship(cell: CellRef, ship: Dyn<Ship>) -> Self {
-        Self {
-            cell,
-            sank_ship: Some(ship),
+    /// The best thing the move did, none for a sonar ping that didn't open anything
+    pub fn result(&self) -> Option<ShotResult> {
+        if !self.sank_ships.is_empty() {
+            Some(ShotResult::Sunk)
+        } else if self.is_hit() {
+            Some(ShotResult::Hit)
+        } else if self.cells.is_empty() && !self.pinged.is_empty() {
+            None
+        } else {
+            Some(ShotResult::Miss)
         }
     }
+
+    fn merge(&mut self, other: Self) {
+        self.cells.extend(other.cells);
+        self.sank_ships.extend(other.sank_ships);
+        self.outlines.extend(other.outlines);
+        self.mines += other.mines;
+        self.pinged.extend(other.pinged);
+        self.fired.extend(other.fired);
+    }
+
+    fn outline(&self, point: Point) -> Option<Outline> {
+        self.outlines
+            .iter()
+            .find(|&&(sunk, _)| sunk == point)
+            .map(|&(_, outline)| outline)
+    }
 }
 
+#[derive(Clone)]
 struct Ship {
     length: u8,
-    nearby_cells: Vec<CellRef>,
-    counter: Dyn<ShipCounter>,
+    cells: Vec<Point>,
+    /// Exposed once the ship sinks, left empty when ships may touch
+    nearby_cells: Vec<Point>,
+    /// Index of the ship definition and its counter
+    kind: usize,
 }
 
 impl Ship {
-    // Returns extra cells to be updated
-    async fn hit(&mut self) -> Option<Vec<CellRef>> {
+    // Returns true if this hit sank the ship
+    fn hit(&mut self) -> bool {
         match self.length.checked_sub(1) {
-            None => return None, // Ship already sank
+            None => false, // Ship already sank
             Some(new_len) => {
                 self.length = new_len;
+                self.has_sank()
             }
         }
+    }
 
-        if self.has_sank() {
-            self.register_sink().await;
-            Some(self.nearby_cells.clone())
-        } else {
-            None // No extra updates needed
+    // Takes back a hit, the ship is afloat again if it sank
+    fn unhit(&mut self) {
+        if usize::from(self.length) < self.cells.len() {
+            self.length += 1;
         }
     }
 
@@ -195,34 +274,64 @@ impl Ship {
         self.length == 0
     }
 
-    async fn register_sink(&mut self) {
-        self.counter.write().await.decrease();
+    // Sides of the cell that lie on the ship's outline
+    fn outline(&self, point: Point) -> Outline {
+        let open = |dx, dy| {
+            point
+                .try_add_delta(dx, dy)
+                .is_none_or(|near| !self.cells.contains(&near))
+        };
 
-        for cell in &self.nearby_cells {
-            cell.write().await.expose();
+        Outline {
+            top: open(-1, 0),
+            right: open(0, 1),
+            bottom: open(1, 0),
+            left: open(0, -1),
         }
     }
 }
 
-// TODO: make this flat
-// Requires drawing ui in a clever way, not inline.
-type Vec2D<T> = Vec<Vec<T>>;
+/// Edges of a sunk ship's cell that are drawn to show the ship's shape
+#[derive(Clone, Copy)]
+struct Outline {
+    top: bool,
+    right: bool,
+    bottom: bool,
+    left: bool,
+}
 
+#[derive(Clone)]
 struct ShipCounter {
+    kind: usize,
     name: String,
+    length: u8,
+    shape: Shape,
     total: u8,
     remaining: u8,
 }
 
 impl ShipCounter {
-    fn new(name: String, n: u8) -> Self {
+    fn new(kind: usize, name: String, shape: Shape, n: u8) -> Self {
         Self {
+            kind,
             name,
+            length: shape.len() as u8,
+            shape,
             total: n,
             remaining: n,
         }
     }
 
+    // Back to the definition it was made from
+    fn definition(&self) -> ShipDefinition {
+        ShipDefinition {
+            name: self.name.clone(),
+            length: self.length,
+            count: self.total,
+            shape: (!self.shape.is_line()).then(|| self.shape.clone()),
+        }
+    }
+
     fn is_defeated(&self) -> bool {
         self.remaining == 0
     }
@@ -230,69 +339,283 @@ impl ShipCounter {
     fn decrease(&mut self) {
         self.remaining.sub_assign(1);
     }
+
+    fn increase(&mut self) {
+        self.remaining = (self.remaining + 1).min(self.total);
+    }
 }
 
+#[derive(Clone)]
 pub struct Board {
-    ships: Vec<Dyn<Ship>>,
-    ship_counters: Vec<Dyn<ShipCounter>>,
-    state: Vec2D<Dyn<CellState>>,
+    bounds: Bounds,
+    /// Cells row by row, see [`Board::index`]
+    state: Vec<CellState>,
+    ships: Vec<Ship>,
+    ship_counters: Vec<ShipCounter>,
+    /// Every shot fired at the board, in order
+    shots: Vec<Point>,
+    /// Every sonar ping made at the board, in order
+    pings: Vec<weapons::Ping>,
+    alphabet: Alphabet,
+    /// Ships may be placed right next to each other, see [`Rules::ships_touch`]
+    ships_touch: bool,
 }
 
 impl Board {
-    fn get_cell(&self, point: Point) -> Option<CellRef> {
-        Some(CellRef {
+    fn new(bounds: Bounds) -> Self {
+        Self {
+            bounds,
+            state: vec![CellState::default(); bounds.x as usize * bounds.y as usize],
+            ships: Vec::new(),
+            ship_counters: Vec::new(),
+            shots: Vec::new(),
+            pings: Vec::new(),
+            alphabet: Alphabet::default(),
+            ships_touch: false,
+        }
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        if point.x >= self.bounds.x || point.y >= self.bounds.y {
+            return None;
+        }
+        Some(point.x as usize * self.bounds.y as usize + point.y as usize)
+    }
+
+    fn get_cell(&self, point: Point) -> Option<&CellState> {
+        self.state.get(self.index(point)?)
+    }
+
+    fn get_cell_mut(&mut self, point: Point) -> Option<&mut CellState> {
+        let index = self.index(point)?;
+        self.state.get_mut(index)
+    }
+
+    fn cells(&self) -> impl Iterator<Item = (Point, &CellState)> {
+        let width = self.bounds.y as usize;
+        self.state
+            .iter()
+            .enumerate()
+            .map(move |(i, cell)| (Point::from_index(i / width, i % width), cell))
+    }
+
+    fn rows(&self) -> impl Iterator<Item = &[CellState]> {
+        self.state.chunks(self.bounds.y as usize)
+    }
+
+    pub fn hit(&mut self, point: Point) -> WebResult<HitDisplayDiff> {
+        let mut diff = self.expose(point)?;
+        self.shots.push(point);
+
+        diff.fired.push(Shot {
             point,
-            accessor: self
-                .state
-                .get(point.x as usize)?
-                .get(point.y as usize)
-                .cloned()?,
-        })
+            result: diff.result().unwrap_or(ShotResult::Miss),
+            sunk: diff.sank_ships.first().map(|counter| counter.name.clone()),
+        });
+
+        // Mines opened around a sunk ship don't go off
+        if self
+            .get_cell(point)
+            .is_some_and(|cell| matches!(cell.content, CellContent::Mine))
+        {
+            diff.mines += 1;
+        }
+        Ok(diff)
+    }
+
+    /// Fires a whole salvo at once, combining what every shot changed.
+    /// Nothing is fired unless every cell can be shot at.
+    pub fn hit_many(&mut self, points: &[Point]) -> WebResult<HitDisplayDiff> {
+        for (i, &point) in points.iter().enumerate() {
+            let cell = self.get_cell(point).ok_or(
+                anyhow!("Invalid cell coordinates")
+                    .client_error()
+                    .code(StatusCode::NOT_FOUND),
+            )?;
+
+            if cell.exposed || points[..i].contains(&point) {
+                return Err(anyhow!("Cell {point} can't be shot at")
+                    .client_error()
+                    .code(StatusCode::CONFLICT));
+            }
+        }
+
+        let mut diff = HitDisplayDiff::default();
+
+        for &point in points {
+            // Already opened around a ship sunk earlier in the salvo
+            if self.get_cell(point).is_some_and(|cell| cell.exposed) {
+                continue;
+            }
+            diff.merge(self.hit(point)?);
+        }
+
+        Ok(diff)
     }
 
-    pub async fn hit(&self, point: Point) -> WebResult<HitDisplayDiff> {
-        let cell = self.get_cell(point).ok_or(
+    // Same as a hit, but doesn't count as a shot
+    fn expose(&mut self, point: Point) -> WebResult<HitDisplayDiff> {
+        let cell = self.get_cell_mut(point).ok_or(
             anyhow!("Invalid cell coordinates")
                 .client_error()
                 .code(StatusCode::NOT_FOUND),
         )?;
 
-        let sank_ship = match cell.hit().await? {
-            Some(ship) => {
-                if ship.read().await.has_sank() {
-                    Some(ship)
-                } else {
-                    None
-                }
+        if cell.exposed {
+            return Err(anyhow!("Cell already hit").into());
+        }
+        cell.expose();
+
+        let mut diff = HitDisplayDiff {
+            cells: vec![(point, *cell)],
+            ..Default::default()
+        };
+
+        if let Some(ship) = cell.get_ship()
+            && self.ships[ship].hit()
+        {
+            self.register_sink(ship, &mut diff);
+        }
+
+        Ok(diff)
+    }
+
+    fn register_sink(&mut self, ship: usize, diff: &mut HitDisplayDiff) {
+        let ship = &self.ships[ship];
+
+        let counter = &mut self.ship_counters[ship.kind];
+        counter.decrease();
+        diff.sank_ships.push(counter.clone());
+
+        for &point in &ship.cells {
+            diff.outlines.push((point, ship.outline(point)));
+
+            if let Some(cell) = self.get_cell(point)
+                && !diff.cells.iter().any(|&(changed, _)| changed == point)
+            {
+                diff.cells.push((point, *cell));
+            }
+        }
+
+        for &point in &ship.nearby_cells {
+            if let Some(index) = self.index(point) {
+                let cell = &mut self.state[index];
+                cell.expose();
+                diff.cells.push((point, *cell));
             }
-            None => None,
+        }
+    }
+
+    /// Takes back the last shot at the board, hiding again whatever it opened
+    fn unhit(&mut self, point: Point) -> WebResult<HitDisplayDiff> {
+        if self.shots.last() != Some(&point) {
+            return Err(anyhow!("Cell {point} wasn't the last one shot at").into());
+        }
+        self.shots.pop();
+
+        let cell = self
+            .get_cell_mut(point)
+            .ok_or(anyhow!("Invalid cell coordinates"))?;
+        cell.conceal();
+
+        let mut diff = HitDisplayDiff {
+            cells: vec![(point, *cell)],
+            ..Default::default()
         };
 
-        Ok(match sank_ship {
-            Some(ship) => HitDisplayDiff::sank_ship(cell, ship),
-            None => HitDisplayDiff::single_cell(cell),
-        })
+        if let Some(ship) = cell.get_ship() {
+            if self.ships[ship].has_sank() {
+                self.register_raise(ship, &mut diff);
+            }
+            self.ships[ship].unhit();
+        }
+
+        Ok(diff)
+    }
+
+    // Undoes what [`Board::register_sink`] did
+    fn register_raise(&mut self, ship: usize, diff: &mut HitDisplayDiff) {
+        let raised = &self.ships[ship];
+
+        let counter = &mut self.ship_counters[raised.kind];
+        counter.increase();
+        diff.sank_ships.push(counter.clone());
+
+        // The rest of the ship is drawn again without the outline
+        for &point in &raised.cells {
+            if let Some(cell) = self.get_cell(point)
+                && cell.exposed
+            {
+                diff.cells.push((point, *cell));
+            }
+        }
+
+        // Cells around it stay open if they were shot at or border another sunk ship
+        for &point in &raised.nearby_cells {
+            let open = self.shots.contains(&point)
+                || self.ships.iter().enumerate().any(|(other, near)| {
+                    other != ship && near.has_sank() && near.nearby_cells.contains(&point)
+                });
+
+            if let Some(index) = self.index(point)
+                && !open
+            {
+                let cell = &mut self.state[index];
+                cell.conceal();
+                diff.cells.push((point, *cell));
+            }
+        }
     }
 
-    pub async fn is_win(&self) -> bool {
-        // TODO: if we can do counters without RwLock,
-        // this can be a much cleaner .iter().map(...).all()
+    /// Opens a random cell of a ship for every mine the owner of the board struck
+    fn reveal(&mut self, mines: usize) -> Vec<HitDisplayDiff> {
+        let mut diffs = Vec::new();
+
+        for _ in 0..mines {
+            let hidden: Vec<Point> = self
+                .cells()
+                .filter(|(_, cell)| !cell.exposed && cell.contains_ship())
+                .map(|(point, _)| point)
+                .collect();
 
-        let mut win = true;
-        for counter in &self.ship_counters {
-            let defeated = counter.read().await.is_defeated();
-            if !defeated {
-                win = false;
+            let Some(&point) = hidden.choose(&mut rand::rng()) else {
                 break;
+            };
+
+            if let Ok(diff) = self.expose(point) {
+                diffs.push(diff);
             }
         }
-        win
+        diffs
+    }
+
+    fn hidden_cells(&self) -> Vec<Point> {
+        self.cells()
+            .filter(|(_, cell)| !cell.exposed)
+            .map(|(point, _)| point)
+            .collect()
+    }
+
+    // Outline of the cell if it belongs to a sunk ship
+    fn sunk_outline(&self, point: Point) -> Option<Outline> {
+        let ship = &self.ships[self.get_cell(point)?.get_ship()?];
+        ship.has_sank().then(|| ship.outline(point))
+    }
+
+    fn ships_afloat(&self) -> usize {
+        self.ships.iter().filter(|ship| !ship.has_sank()).count()
+    }
+
+    pub fn is_win(&self) -> bool {
+        self.ship_counters.iter().all(ShipCounter::is_defeated)
     }
 }
 
-enum ShipAddError {
-    Collision { point: Point },
+pub enum ShipAddError {
+    Collision { point: Point, alphabet: Alphabet },
+    Blocked { point: Point, alphabet: Alphabet },
     OutOfBounds,
+    NoneRemaining,
     InternalError(anyhow::Error),
 }
 
@@ -302,11 +625,33 @@ impl From<&str> for ShipAddError {
     }
 }
 
-#[derive(Clone)]
+/// The whole fleet couldn't be placed on the board
+#[derive(Debug)]
+pub enum FitError {
+    /// No layout exists, `ship` can't be placed along with the ones before it
+    NoRoom { ship: ShipDefinition },
+    /// The search ran out of budget before finding a layout, so one may still exist.
+    /// Dense fleets can end up here, proving they don't fit is best effort.
+    GaveUp { ship: ShipDefinition },
+}
+
+impl std::error::Error for FitError {}
+
+/// A shape has more cells than a ship can have
+#[derive(Debug)]
+pub struct ShapeTooLarge;
+
+impl std::error::Error for ShapeTooLarge {}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ShipDefinition {
     name: String,
+    /// Number of cells the ship takes
     length: u8,
     count: u8,
+    /// Left out for straight ships
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shape: Option<Shape>,
 }
 
 impl ShipDefinition {
@@ -315,53 +660,253 @@ impl ShipDefinition {
             name: name.to_string(),
             length,
             count,
+            shape: None,
         }
     }
 
-    fn to_counter(self) -> ShipCounter {
-        ShipCounter::new(self.name, self.count)
+    /// Gives the ship a shape of its own, its length becomes the number of cells.
+    /// Straight shapes are kept as plain lengths.
+    pub fn with_shape(mut self, shape: Shape) -> Result<Self, ShapeTooLarge> {
+        self.length = u8::try_from(shape.len()).map_err(|_| ShapeTooLarge)?;
+        self.shape = (!shape.is_line()).then_some(shape);
+        Ok(self)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn length(&self) -> u8 {
+        self.length
+    }
+
+    pub fn count(&self) -> u8 {
+        self.count
+    }
+
+    /// Shape given with [`Self::with_shape`], if any
+    pub fn custom_shape(&self) -> Option<&Shape> {
+        self.shape.as_ref()
+    }
+
+    pub fn shape(&self) -> Shape {
+        self.shape
+            .clone()
+            .unwrap_or_else(|| Shape::line(self.length))
+    }
+
+    pub fn classic() -> Vec<Self> {
+        vec![
+            Self::new("Линкор", 4, 1),
+            Self::new("Крейсер", 3, 2),
+            Self::new("Эсминец", 2, 3),
+            Self::new("Торпеда", 1, 4),
+        ]
+    }
+
+    fn to_counter(&self, kind: usize) -> ShipCounter {
+        ShipCounter::new(kind, self.name.clone(), self.shape(), self.count)
+    }
+}
+
+/// Quarter turns clockwise from the way a ship's shape is defined.
+/// Straight ships start out lying along a row.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Orientation {
+    #[default]
+    #[serde(alias = "Horizontal")]
+    Right,
+    #[serde(alias = "Vertical")]
+    Down,
+    Left,
+    Up,
+}
+
+impl Orientation {
+    pub const ALL: [Self; 4] = [Self::Right, Self::Down, Self::Left, Self::Up];
+
+    pub fn rotate(self) -> Self {
+        match self {
+            Self::Right => Self::Down,
+            Self::Down => Self::Left,
+            Self::Left => Self::Up,
+            Self::Up => Self::Right,
+        }
+    }
+
+    fn turns(self) -> u8 {
+        match self {
+            Self::Right => 0,
+            Self::Down => 1,
+            Self::Left => 2,
+            Self::Up => 3,
+        }
+    }
+
+    pub fn is_horizontal(self) -> bool {
+        matches!(self, Self::Right | Self::Left)
     }
 }
 
+// What it takes to place a kind of ship, worked out once per fleet
+struct Footprint {
+    variants: Vec<Shape>,
+    /// See [`Shape::halo_area`]
+    halo_area: usize,
+    /// See [`Shape::blocks`]
+    blocks: usize,
+}
+
+impl Footprint {
+    fn of(ship: &ShipDefinition) -> Self {
+        let shape = ship.shape();
+
+        Self {
+            variants: shape.variants(),
+            halo_area: shape.halo_area(),
+            blocks: shape.blocks(),
+        }
+    }
+}
+
+/// One attempt at placing the rest of the fleet
+struct Search<'a, R> {
+    queue: Vec<usize>,
+    rng: &'a mut R,
+    // Prefer positions that waste the least space, for fleets that barely fit
+    tight: bool,
+    /// Positions left to look at, the search is exhaustive if some remain
+    budget: usize,
+    deepest: usize,
+}
+
+#[derive(Clone)]
 pub struct BoardBuilder {
     bounds: Point,
+    fleet: Vec<ShipDefinition>,
+    /// One for each ship definition, shared between copies made while searching
+    footprints: Arc<[Footprint]>,
+    placed: Vec<u8>,
     inner: Board,
 }
 
 impl BoardBuilder {
     fn new(bounds: Bounds) -> Self {
-        let state = (0..bounds.x)
-            .map(|_| {
-                (0..bounds.y)
-                    .map(|_| Arc::new(RwLock::new(CellState::default())))
-                    .collect()
-            })
-            .collect();
-
         Self {
             bounds,
-            inner: Board {
-                ship_counters: Vec::new(),
-                ships: Vec::new(),
-                state,
-            },
+            fleet: Vec::new(),
+            footprints: Arc::new([]),
+            placed: Vec::new(),
+            inner: Board::new(bounds),
         }
     }
 
+    #[cfg(test)]
     pub fn square(n: u8) -> Self {
         Self::new(Bounds { x: n, y: n })
     }
 
-    async fn add_ship_instance(
-        &mut self,
-        counter: &Dyn<ShipCounter>,
-        points: Vec<Point>,
-    ) -> Result<(), ShipAddError> {
+    // Rows go along x, columns along y
+    pub fn rectangle(width: u8, height: u8) -> Self {
+        Self::new(Bounds {
+            x: height,
+            y: width,
+        })
+    }
+
+    pub fn alphabet(mut self, alphabet: Alphabet) -> Self {
+        self.inner.alphabet = alphabet;
+        self
+    }
+
+    pub fn ships_touch(mut self, ships_touch: bool) -> Self {
+        self.inner.ships_touch = ships_touch;
+        self
+    }
+
+    /// Scatters islands and mines over open water, before any ship is placed
+    pub fn terrain(mut self, islands: u8, mines: u8, rng: &mut impl Rng) -> Self {
+        // Classic games must draw the same boards from the same seed as before
+        if islands == 0 && mines == 0 {
+            return self;
+        }
+
+        let mut water: Vec<usize> = (0..self.inner.state.len())
+            .filter(|&i| matches!(self.inner.state[i].content, CellContent::Water))
+            .collect();
+        water.shuffle(rng);
+
+        let (land, mined) = water.split_at(water.len().min(islands.into()));
+
+        for &i in land {
+            let cell = &mut self.inner.state[i];
+            cell.content = CellContent::Island;
+            cell.expose();
+        }
+
+        for &i in mined.iter().take(mines.into()) {
+            self.inner.state[i].content = CellContent::Mine;
+        }
+        self
+    }
+
+    // The same board with its islands and mines, but without a fleet
+    fn bare(&self) -> Self {
+        let mut bare = Self::new(self.bounds)
+            .alphabet(self.inner.alphabet)
+            .ships_touch(self.inner.ships_touch);
+
+        for (cell, &old) in bare.inner.state.iter_mut().zip(&self.inner.state) {
+            if old.is_terrain() {
+                *cell = old;
+            }
+        }
+        bare
+    }
+
+    // The same board and fleet with no ships placed yet
+    fn empty(&self) -> Self {
+        self.bare().fleet(&self.fleet)
+    }
+
+    pub fn fleet(mut self, ships: &[ShipDefinition]) -> Self {
+        for ship in ships {
+            let kind = self.fleet.len();
+            self.inner.ship_counters.push(ship.to_counter(kind));
+            self.fleet.push(ship.clone());
+            self.placed.push(0);
+        }
+        self.footprints = self.fleet.iter().map(Footprint::of).collect();
+        self
+    }
+
+    fn remaining(&self, kind: usize) -> u8 {
+        self.fleet[kind].count - self.placed[kind]
+    }
+
+    fn is_complete(&self) -> bool {
+        (0..self.fleet.len()).all(|kind| self.remaining(kind) == 0)
+    }
+
+    fn clear(&mut self) {
+        *self = self.empty();
+    }
+
+    // Takes the finished board out of the builder, leaving it empty
+    fn finish(&mut self) -> Option<Board> {
+        if !self.is_complete() {
+            return None;
+        }
+
+        let empty = self.empty();
+        Some(std::mem::replace(self, empty).inner)
+    }
+
+    fn add_ship_instance(&mut self, kind: usize, points: Vec<Point>) -> Result<(), ShipAddError> {
         if points.is_empty() {
             return Err("Ship requires at least one point".into());
         };
 
-        let mut ship_cells = Vec::new();
         let mut near_cells = Vec::new();
 
         for &point in &points {
@@ -370,11 +915,25 @@ impl BoardBuilder {
                 .get_cell(point)
                 .ok_or(ShipAddError::OutOfBounds)?;
 
-            if cell.read().await.get_collision().is_some() {
-                return Err(ShipAddError::Collision { point }); // TODO: maybe return ship here
+            if cell.is_terrain() {
+                return Err(ShipAddError::Blocked {
+                    point,
+                    alphabet: self.inner.alphabet,
+                });
             }
 
-            let mut tried_points = HashSet::new();
+            if self.collides(cell) {
+                // TODO: maybe return ship here
+                return Err(ShipAddError::Collision {
+                    point,
+                    alphabet: self.inner.alphabet,
+                });
+            }
+
+            // Nothing around the ship is off limits or given away when it sinks
+            if self.inner.ships_touch {
+                continue;
+            }
 
             // Collect adjacent points (including diagonals) for collision checking
             for dx in -1..=1 {
@@ -382,100 +941,453 @@ impl BoardBuilder {
                     if let Some(adjacent_point) = point.try_add_delta(dx, dy) {
                         // Only add if it's not part of the ship itself,
                         // and we haven't reached the same point via delta from another cell
+                        // Islands are open already, mines nearby are opened when the ship sinks
                         if !points.contains(&adjacent_point)
-                            && !tried_points.contains(&adjacent_point)
+                            && !near_cells.contains(&adjacent_point)
+                            && let Some(cell) = self.inner.get_cell(adjacent_point)
+                            && !matches!(cell.content, CellContent::Island)
                         {
-                            tried_points.insert(adjacent_point);
-
-                            if let Some(cell) = self.inner.get_cell(adjacent_point) {
-                                // TODO: is this check redundant
-                                // considering we checked for collisions above?
-                                if cell.read().await.contains_ship() {
-                                    return Err(ShipAddError::Collision {
-                                        point: adjacent_point,
-                                    });
-                                }
-                                near_cells.push(cell);
+                            // TODO: is this check redundant
+                            // considering we checked for collisions above?
+                            if cell.contains_ship() {
+                                return Err(ShipAddError::Collision {
+                                    point: adjacent_point,
+                                    alphabet: self.inner.alphabet,
+                                });
                             }
+                            near_cells.push(adjacent_point);
                         }
                     }
                 }
             }
-            ship_cells.push(cell);
         }
 
         // No collisions detected, proceed with placing the ship
-        let ship = Arc::new(RwLock::new(Ship {
-            length: points.len() as u8,
-            nearby_cells: near_cells.clone(),
-            counter: counter.clone(),
-        }));
-
-        self.inner.ships.push(ship.clone());
+        let ship = self.inner.ships.len();
 
-        for cell in ship_cells {
-            cell.write().await.content = CellContent::Ship(ship.clone())
+        for &point in &points {
+            if let Some(cell) = self.inner.get_cell_mut(point) {
+                cell.content = CellContent::Ship(ship);
+            }
         }
 
-        for cell in near_cells {
-            cell.write().await.content = CellContent::NearShip(ship.clone())
+        for &point in &near_cells {
+            if let Some(cell) = self.inner.get_cell_mut(point)
+                && !cell.is_terrain()
+            {
+                cell.content = CellContent::NearShip(ship);
+            }
         }
 
+        self.inner.ships.push(Ship {
+            length: points.len() as u8,
+            cells: points,
+            nearby_cells: near_cells,
+            kind,
+        });
+
         Ok(())
     }
 
-    fn add_ship_manual(&mut self) -> Result<(), ShipAddError> {
-        todo!()
+    fn add_ship(&mut self, kind: usize, points: Vec<Point>) -> Result<(), ShipAddError> {
+        if self.remaining(kind) == 0 {
+            return Err(ShipAddError::NoneRemaining);
+        }
+
+        self.add_ship_instance(kind, points)?;
+        self.placed[kind] += 1;
+
+        Ok(())
     }
 
-    async fn add_ship_random(&mut self, length: u8, counter: &Dyn<ShipCounter>) -> Result<()> {
-        static TRIES: u16 = 1000;
+    fn add_ship_manual(
+        &mut self,
+        kind: usize,
+        start: Point,
+        orientation: Orientation,
+        flipped: bool,
+    ) -> Result<(), ShipAddError> {
+        let ship = self.fleet.get(kind).ok_or("Unknown ship kind")?;
+        let points = ship
+            .shape()
+            .oriented(orientation, flipped)
+            .points(start)
+            .ok_or(ShipAddError::OutOfBounds)?;
+
+        self.add_ship(kind, points)
+    }
 
-        // TODO: less rng cell bindings
+    // Whether a ship can't take the cell
+    fn collides(&self, cell: &CellState) -> bool {
+        match self.inner.ships_touch {
+            true => cell.contains_ship() || cell.is_terrain(),
+            false => cell.get_collision().is_some() || cell.is_terrain(),
+        }
+    }
 
-        for _ in 0..1000 {
-            let horizontal = rand::rng().random_bool(0.5);
+    // Points of a ship of `kind` at position `id`, if it fits there.
+    // Positions enumerate every start cell with every distinct way to lay the ship.
+    fn position(&self, kind: usize, id: usize) -> Option<Vec<Point>> {
+        let variants = &self.footprints[kind].variants;
+        let cell = id / variants.len();
+
+        let width = self.bounds.y as usize;
+        let start = Point::from_index(cell / width, cell % width);
+        let points = variants[id % variants.len()].points(start)?;
+
+        points
+            .iter()
+            .all(|&point| {
+                self.inner
+                    .get_cell(point)
+                    .is_some_and(|cell| !self.collides(cell))
+            })
+            .then_some(points)
+    }
 
-            let (dx, dy) = if horizontal { (length, 1) } else { (1, length) };
-            let bounds = Bounds {
-                x: self.bounds.x.saturating_sub(dx.into()),
-                y: self.bounds.y.saturating_sub(dy.into()),
-            };
+    fn positions(&self, kind: usize) -> impl Iterator<Item = usize> {
+        0..self.inner.state.len() * self.footprints[kind].variants.len()
+    }
 
-            let start_x = rand::rng().random_range(0..=bounds.x);
-            let start_y = rand::rng().random_range(0..=bounds.y);
+    // Ships can't share a 2x2 block without touching,
+    // so blocks with no ship and some room left bound what still fits
+    fn free_blocks(&self) -> usize {
+        let mut free = 0;
 
-            let points: Vec<Point> = (0..length)
-                .map(|i| {
-                    // Add length according to orientation
-                    let (dx, dy) = if horizontal { (i, 0) } else { (0, i) };
+        for bx in (0..self.bounds.x).step_by(2) {
+            for by in (0..self.bounds.y).step_by(2) {
+                let mut taken = false;
+                let mut open = false;
 
-                    Point {
-                        x: start_x + dx,
-                        y: start_y + dy,
+                for (dx, dy) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+                    if let Some(cell) = self.inner.get_cell(Point::new(bx + dx, by + dy)) {
+                        taken |= cell.contains_ship();
+                        open |= cell.get_collision().is_none();
                     }
-                })
-                .collect();
+                }
 
-            match self.add_ship_instance(&counter, points).await {
-                Ok(()) => {
-                    return Ok(());
+                if !taken && open {
+                    free += 1;
                 }
-                Err(_) => continue, // Try again with different position
             }
         }
-        bail!("Couldn't place a ship after {TRIES} attempts")
+        free
     }
 
-    pub async fn random(mut self, ships: &[ShipDefinition]) -> Result<Board> {
-        for ship in ships {
-            let counter = Arc::new(RwLock::new(ship.clone().to_counter()));
-            self.inner.ship_counters.push(counter.clone());
+    // Blocks a ship covers at the very least
+    fn blocks(&self, kind: usize) -> usize {
+        self.footprints[kind].blocks
+    }
+
+    fn has_room_for(&self, kinds: &[usize]) -> bool {
+        if self.inner.ships_touch {
+            let needed: usize = kinds
+                .iter()
+                .map(|&kind| self.fleet[kind].length as usize)
+                .sum();
+            let free = self
+                .inner
+                .state
+                .iter()
+                .filter(|cell| !cell.contains_ship())
+                .count();
+            return needed <= free;
+        }
+
+        let needed: usize = kinds.iter().map(|&kind| self.blocks(kind)).sum();
+        needed <= self.free_blocks()
+    }
+
+    // Finds the first ship in `queue` that can't fit whatever the layout
+    fn overflow(&self, queue: &[usize]) -> Option<usize> {
+        // Touching ships only need their own cells
+        if self.inner.ships_touch {
+            let mut needed: usize = self.inner.ships.iter().map(|ship| ship.cells.len()).sum();
+            return (0..queue.len()).find(|&i| {
+                needed += self.fleet[queue[i]].length as usize;
+                needed > self.inner.state.len()
+            });
+        }
+
+        // A ship grown by its halo to the bottom and right takes an area
+        // that never overlaps another one on a board grown by one row and column
+        let area = (self.bounds.x as usize + 1) * (self.bounds.y as usize + 1);
+        let placed: usize = self
+            .inner
+            .ships
+            .iter()
+            .map(|ship| self.footprints[ship.kind].halo_area)
+            .sum();
+
+        let free = self.free_blocks();
+
+        let mut needed = placed;
+        let mut blocks = 0;
+        (0..queue.len()).find(|&i| {
+            needed += self.footprints[queue[i]].halo_area;
+            blocks += self.blocks(queue[i]);
+            needed > area || blocks > free
+        })
+    }
+
+    // Indices of the cells a ship at `points` covers or touches
+    fn surroundings(&self, points: &[Point]) -> Vec<usize> {
+        let mut indices: Vec<usize> = points
+            .iter()
+            .flat_map(|point| {
+                (-1..=1)
+                    .flat_map(move |dx| (-1..=1).filter_map(move |dy| point.try_add_delta(dx, dy)))
+            })
+            .filter_map(|point| self.inner.index(point))
+            .collect();
+
+        indices.sort_unstable();
+        indices.dedup();
+        indices
+    }
+
+    // Cells that would become unusable for other ships
+    fn waste(&self, points: &[Point]) -> usize {
+        self.surroundings(points)
+            .into_iter()
+            .filter(|&index| self.inner.state[index].get_collision().is_none())
+            .count()
+    }
+
+    // Takes back the last ship, putting back the cells saved before it was added
+    fn undo_ship(&mut self, kind: usize, saved: Vec<(usize, CellContent)>) {
+        for (index, content) in saved {
+            self.inner.state[index].content = content;
+        }
+        self.inner.ships.pop();
+        self.placed[kind] -= 1;
+    }
+
+    // Places `search.queue[depth..]`, undoing earlier choices when a later ship doesn't fit
+    fn place_from<R: Rng>(
+        &mut self,
+        search: &mut Search<R>,
+        depth: usize,
+        after: Option<usize>,
+    ) -> bool {
+        let Some(&kind) = search.queue.get(depth) else {
+            return true;
+        };
+        search.deepest = search.deepest.max(depth);
+
+        if !self.has_room_for(&search.queue[depth..]) {
+            return false;
+        }
+
+        // Ships of the same kind are interchangeable, only try them in one order
+        let mut positions: Vec<usize> = self
+            .positions(kind)
+            .filter(|&position| after.is_none_or(|after| position > after))
+            .collect();
+        positions.shuffle(search.rng);
+
+        if search.tight {
+            // Every position gets looked at to rank them
+            if search.budget < positions.len() {
+                search.budget = 0;
+                return false;
+            }
+            search.budget -= positions.len();
+
+            positions.sort_by_cached_key(|&position| {
+                self.position(kind, position)
+                    .map_or(usize::MAX, |points| self.waste(&points))
+            });
+        }
+
+        for position in positions {
+            if search.budget == 0 {
+                return false;
+            }
+            search.budget -= 1;
+
+            let Some(points) = self.position(kind, position) else {
+                continue;
+            };
+
+            let saved: Vec<(usize, CellContent)> = self
+                .surroundings(&points)
+                .into_iter()
+                .map(|index| (index, self.inner.state[index].content))
+                .collect();
+
+            if self.add_ship(kind, points).is_err() {
+                continue;
+            }
+
+            let after = match search.queue.get(depth + 1) {
+                Some(&following) if following == kind && !search.tight => Some(position),
+                _ => None,
+            };
 
-            for _ in 0..ship.count {
-                self.add_ship_random(ship.length, &counter).await?
+            if self.place_from(search, depth + 1, after) {
+                return true;
             }
+            self.undo_ship(kind, saved);
         }
+        false
+    }
+
+    /// Places the remaining ships, or leaves the board as is if they don't fit.
+    /// Fleets that are too big for the board are ruled out right away,
+    /// otherwise the search backtracks through every layout until it runs out of budget.
+    /// Only a search that ran its course proves there is no room,
+    /// on dense fleets the budget often runs out first.
+    fn fill_random(&mut self, rng: &mut impl Rng) -> Result<(), FitError> {
+        // Positions looked at in one attempt before starting over
+        const BUDGET: usize = 1_000_000;
+
+        // Long ships are the hardest to fit, so they go first
+        let mut queue: Vec<usize> = (0..self.fleet.len())
+            .flat_map(|kind| std::iter::repeat_n(kind, self.remaining(kind).into()))
+            .collect();
+        queue.sort_by_key(|&kind| Reverse(self.fleet[kind].length));
+
+        if let Some(overflow) = self.overflow(&queue) {
+            return Err(FitError::NoRoom {
+                ship: self.fleet[queue[overflow]].clone(),
+            });
+        }
+
+        let mut search = Search {
+            queue,
+            rng,
+            tight: false,
+            budget: BUDGET,
+            deepest: 0,
+        };
+
+        // Spread out placements first, packing ships tightly if that doesn't work
+        for tight in [false, true, true, true] {
+            search.tight = tight;
+            search.budget = BUDGET;
+
+            if self.place_from(&mut search, 0, None) {
+                return Ok(());
+            }
+
+            // Every layout was tried, so there is none
+            if search.budget > 0 {
+                return Err(FitError::NoRoom {
+                    ship: self.fleet[search.queue[search.deepest]].clone(),
+                });
+            }
+        }
+
+        Err(FitError::GaveUp {
+            ship: self.fleet[search.queue[search.deepest]].clone(),
+        })
+    }
+
+    /// Places the whole fleet at random, the same rng state always gives the same board
+    pub fn random(
+        mut self,
+        ships: &[ShipDefinition],
+        rng: &mut impl Rng,
+    ) -> Result<Board, FitError> {
+        self = self.fleet(ships);
+        self.fill_random(rng)?;
         Ok(self.inner)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_points_within_bounds() {
+        let bounds = Point::new(10, 12);
+
+        assert_eq!(
+            Point::parse_within("0-0", bounds).unwrap(),
+            Point::new(0, 0)
+        );
+        assert_eq!(
+            Point::parse_within("9-11", bounds).unwrap(),
+            Point::new(9, 11)
+        );
+
+        for outside in ["10-0", "0-12", "255-255"] {
+            assert!(Point::parse_within(outside, bounds).is_err());
+        }
+        for garbled in ["", "3", "3-", "-3", "a-b", "-1-2", "256-0"] {
+            assert!(Point::parse_within(garbled, bounds).is_err());
+        }
+    }
+
+    fn fit(size: u8, ships_touch: bool, ships: &[ShipDefinition]) -> Result<Board, FitError> {
+        BoardBuilder::square(size)
+            .ships_touch(ships_touch)
+            .random(ships, &mut seeded_rng(1))
+    }
+
+    fn no_room(result: Result<Board, FitError>) -> bool {
+        matches!(result, Err(FitError::NoRoom { .. }))
+    }
+
+    fn square(count: u8) -> ShipDefinition {
+        ShipDefinition::new("Квадрат", 4, count)
+            .with_shape(Shape::from("##/##".to_string()))
+            .unwrap()
+    }
+
+    #[test]
+    fn places_tight_fleets() {
+        // As many ships as the board can take without them touching
+        let singles = fit(5, false, &[ShipDefinition::new("Катер", 1, 9)]).unwrap();
+        assert_eq!(singles.ships.len(), 9);
+
+        let squares = fit(5, false, &[square(4)]).unwrap();
+        assert_eq!(squares.ships.len(), 4);
+    }
+
+    #[test]
+    fn proves_there_is_no_room() {
+        // Ruled out by the bounds before searching
+        let dominoes = fit(4, false, &[ShipDefinition::new("Эсминец", 2, 5)]);
+        assert!(no_room(dominoes));
+
+        // These pass the bounds, the search has to try every layout
+        let lines = [
+            ShipDefinition::new("Линкор", 4, 2),
+            ShipDefinition::new("Крейсер", 3, 2),
+        ];
+        assert!(no_room(fit(5, false, &lines)));
+
+        let tiles = [square(3), ShipDefinition::new("Линкор", 4, 1)];
+        assert!(no_room(fit(4, true, &tiles)));
+    }
+
+    #[test]
+    fn follows_the_touching_rule() {
+        let fleet = [ShipDefinition::new("Линкор", 4, 4)];
+
+        let board = fit(4, true, &fleet).unwrap();
+        assert_eq!(board.ships.len(), 4);
+        assert!(no_room(fit(4, false, &fleet)));
+
+        // Without touching, no ship cell borders another ship
+        let board = fit(10, false, &ShipDefinition::classic()).unwrap();
+        for (i, ship) in board.ships.iter().enumerate() {
+            for &point in &ship.cells {
+                for dx in -1..=1 {
+                    for dy in -1..=1 {
+                        if let Some(cell) = point
+                            .try_add_delta(dx, dy)
+                            .and_then(|near| board.get_cell(near))
+                            && let CellContent::Ship(other) = cell.content
+                        {
+                            assert_eq!(other, i);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::{Result, anyhow, bail};
+use axum::http::StatusCode;
+
+use crate::{
+    game::{Board, BoardBuilder, FitError, Orientation, Point, ShipAddError},
+    utils::errors::{AnyhowWebExt, WebResult},
+};
+
+pub struct Placement {
+    pub(super) builder: BoardBuilder,
+    pub(super) selected: usize,
+    pub(super) orientation: Orientation,
+    /// Whether the ship is mirrored before it's turned
+    pub(super) flipped: bool,
+}
+
+impl Placement {
+    pub fn new(builder: BoardBuilder) -> Self {
+        Self {
+            builder,
+            selected: 0,
+            orientation: Orientation::default(),
+            flipped: false,
+        }
+    }
+
+    pub fn select(&mut self, kind: usize) -> Result<()> {
+        if kind >= self.builder.fleet.len() {
+            bail!("Unknown ship kind");
+        }
+
+        self.selected = kind;
+        Ok(())
+    }
+
+    pub fn rotate(&mut self) {
+        self.orientation = self.orientation.rotate();
+    }
+
+    pub fn flip(&mut self) {
+        self.flipped = !self.flipped;
+    }
+
+    pub fn place(&mut self, start: Point) -> Result<(), ShipAddError> {
+        self.builder
+            .add_ship_manual(self.selected, start, self.orientation, self.flipped)?;
+
+        // Move on to the next ship kind once the current one runs out
+        if self.builder.remaining(self.selected) == 0
+            && let Some(next) =
+                (0..self.builder.fleet.len()).find(|&kind| self.builder.remaining(kind) > 0)
+        {
+            self.selected = next;
+        }
+
+        Ok(())
+    }
+
+    pub fn randomize(&mut self) -> Result<(), FitError> {
+        self.builder.fill_random(&mut rand::rng())
+    }
+
+    pub fn clear(&mut self) {
+        self.builder.clear();
+        self.selected = 0;
+    }
+
+    pub fn finish(&mut self) -> Option<Board> {
+        self.builder.finish()
+    }
+}
+
+pub enum Fleet {
+    Placing(Placement),
+    Ready(Board),
+}
+
+impl Fleet {
+    /// Size of the board, the same while placing and in battle
+    pub fn bounds(&self) -> Point {
+        match self {
+            Self::Placing(placement) => placement.builder.bounds,
+            Self::Ready(board) => board.bounds,
+        }
+    }
+
+    pub fn placing(&mut self) -> WebResult<&mut Placement> {
+        match self {
+            Self::Placing(placement) => Ok(placement),
+            Self::Ready(_) => Err(anyhow!("Fleet is already placed")
+                .client_error()
+                .code(StatusCode::CONFLICT)),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use anyhow::{Result, anyhow, bail};
+
+use crate::game::{
+    Board, BoardBuilder, GameSnapshot, Seat,
+    battle::Move,
+    snapshot::{ControlSnapshot, FleetSnapshot},
+};
+
+/// A finished game put back together up to some move
+pub struct Replay {
+    /// Host's and guest's boards with the moves up to `step` made on them.
+    /// Cells opened by mines aren't moves, so they stay hidden.
+    pub(super) boards: [Board; 2],
+    pub(super) log: Vec<Move>,
+    pub(super) step: usize,
+    /// Whether the guest was the computer
+    pub(super) solo: bool,
+}
+
+impl Replay {
+    /// Goes up to the end of the game if there are fewer moves than `step`
+    pub fn new(snapshot: &GameSnapshot, step: usize) -> Result<Self> {
+        let board = |seat: Seat| match &snapshot.sides[seat.index()].fleet {
+            FleetSnapshot::Ready(board) => Ok(BoardBuilder::restore(board)?.inner),
+            FleetSnapshot::Placing { .. } => bail!("The fleets were never placed"),
+        };
+        let mut boards = [board(Seat::Host)?, board(Seat::Guest)?];
+
+        let step = step.min(snapshot.log.len());
+        for entry in &snapshot.log[..step] {
+            let point = entry.shot.point;
+            boards[entry.seat.other().index()]
+                .hit(point)
+                .map_err(|_| anyhow!("Invalid move at {point}"))?;
+        }
+
+        Ok(Self {
+            boards,
+            log: snapshot.log.clone(),
+            step,
+            solo: matches!(
+                snapshot.sides[Seat::Guest.index()].control,
+                ControlSnapshot::Computer(_)
+            ),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.log.len()
+    }
+}
@@ -0,0 +1,166 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::game::{
+    Alphabet, Arsenal, Board, BoardBuilder, FleetError, FleetReport, Seed, ShipDefinition,
+    seeded_rng,
+};
+
+/// How many shots a player fires each turn
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Salvo {
+    /// One shot at a time
+    #[default]
+    Off,
+    /// The same number of shots every turn
+    Fixed(u8),
+    /// One shot for every ship still afloat
+    PerShip,
+}
+
+/// What striking a mine costs the player who shot at it
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MineEffect {
+    /// The player sits out their next turn
+    #[default]
+    SkipTurn,
+    /// A random cell of one of the player's ships is opened
+    Reveal,
+}
+
+/// Optional rules that change how a game is played
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Rules {
+    /// A hit earns another shot, as in the classic paper game.
+    /// Doesn't apply to salvos.
+    #[serde(default)]
+    pub repeat_on_hit: bool,
+    #[serde(default)]
+    pub salvo: Salvo,
+    /// Ships may be placed side by side, as in the Milton Bradley edition
+    #[serde(default)]
+    pub ships_touch: bool,
+    /// Cells of land on each board, no ship can be placed there or shot at them
+    #[serde(default)]
+    pub islands: u8,
+    /// Hidden mines on each board, see [`MineEffect`]
+    #[serde(default)]
+    pub mines: u8,
+    #[serde(default)]
+    pub mine_effect: MineEffect,
+    /// Special weapons each player gets at the start, none in the classic game
+    #[serde(default)]
+    pub arsenal: Arsenal,
+    /// Shots each player may fire, the one who runs out first loses
+    #[serde(default)]
+    pub max_shots: Option<u16>,
+    /// Minutes the battle may take from the first shot, the player to move loses when it's up
+    #[serde(default)]
+    pub time_limit: Option<u16>,
+}
+
+impl Rules {
+    /// Whether none of the optional rules are on
+    pub fn is_classic(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Everything chosen on the custom game form
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Setup {
+    pub width: u8,
+    pub height: u8,
+    pub ships: Vec<ShipDefinition>,
+    #[serde(default)]
+    pub rules: Rules,
+    #[serde(default)]
+    pub alphabet: Alphabet,
+}
+
+/// Both boards of a custom game, drawn from one seed
+pub struct Boards {
+    pub report: FleetReport,
+    /// The player's board with its terrain, waiting for the fleet
+    pub player: BoardBuilder,
+    pub computer: Board,
+}
+
+impl Default for Setup {
+    fn default() -> Self {
+        Self {
+            width: 10,
+            height: 10,
+            ships: ShipDefinition::classic(),
+            rules: Rules::default(),
+            alphabet: Alphabet::default(),
+        }
+    }
+}
+
+impl Setup {
+    /// Empty board with islands and mines scattered over it
+    pub fn builder(&self, rng: &mut impl Rng) -> BoardBuilder {
+        BoardBuilder::rectangle(self.width, self.height)
+            .alphabet(self.alphabet)
+            .ships_touch(self.rules.ships_touch)
+            .terrain(self.rules.islands, self.rules.mines, rng)
+    }
+
+    /// Draws both boards of a game from `seed` and checks the fleet on each of them.
+    /// The same seed always gives the same boards, so a setup that passed once starts every time.
+    pub fn boards(&self, seed: Seed) -> Result<Boards, FleetError> {
+        let mut rng = seeded_rng(seed);
+        let player = self.builder(&mut rng);
+        let computer = self.builder(&mut rng);
+
+        let ships = self.fleet();
+        let (report, computer) = computer.random_checked(&ships, &mut rng)?;
+        player.validate(&ships, &mut rng)?;
+
+        Ok(Boards {
+            report,
+            player: player.fleet(&ships),
+            computer,
+        })
+    }
+
+    /// Ships that make it into the game, rows set to none of them are left out
+    pub fn fleet(&self) -> Vec<ShipDefinition> {
+        self.ships
+            .iter()
+            .filter(|ship| ship.count() > 0)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Difficulty, Fleet, Game, Opponent, Placement};
+
+    #[test]
+    fn empty_rows_stay_out_of_the_game() {
+        let mut setup = Setup::default();
+        setup.ships.push(ShipDefinition::new("", 0, 0));
+
+        let Ok(boards) = setup.boards(7) else {
+            panic!("classic fleet fits");
+        };
+        let mut placement = Placement::new(boards.player);
+        placement.randomize().expect("classic fleet fits");
+        let board = placement.finish().expect("every ship is placed");
+
+        let game = Game::solo(
+            Fleet::Ready(board),
+            boards.computer,
+            Opponent::new(Difficulty::default()),
+        )
+        .with_rules(setup.rules);
+
+        assert!(Game::restore(&game.snapshot()).is_ok());
+    }
+}
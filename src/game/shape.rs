@@ -0,0 +1,335 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::{Orientation, Point};
+
+/// Cells covered by a ship as (row, column) offsets.
+/// They are kept in reading order and moved into the top left corner,
+/// so the same shape always compares equal.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", from = "String")]
+pub struct Shape(Vec<(u8, u8)>);
+
+impl Shape {
+    /// Straight ship lying along a row
+    pub fn line(length: u8) -> Self {
+        Self((0..length).map(|i| (0, i)).collect())
+    }
+
+    fn from_cells(cells: Vec<(i16, i16)>) -> Self {
+        let top = cells.iter().map(|&(row, _)| row).min().unwrap_or(0);
+        let left = cells.iter().map(|&(_, column)| column).min().unwrap_or(0);
+
+        let mut cells: Vec<(u8, u8)> = cells
+            .into_iter()
+            .map(|(row, column)| ((row - top) as u8, (column - left) as u8))
+            .collect();
+        cells.sort();
+        cells.dedup();
+
+        Self(cells)
+    }
+
+    /// Shape made by cells lying on the board, wherever they are
+    pub(super) fn of_points(points: &[Point]) -> Self {
+        Self::from_cells(
+            points
+                .iter()
+                .map(|point| (point.x.into(), point.y.into()))
+                .collect(),
+        )
+    }
+
+    /// Reads rows of `#` for cells and `.` for gaps, separated by `/`.
+    /// Anything else gives an empty shape, which never passes validation.
+    fn parse(s: &str) -> Self {
+        let mut cells = Vec::new();
+
+        for (row, line) in s.trim().split('/').enumerate() {
+            for (column, char) in line.trim().chars().enumerate() {
+                if row >= u8::MAX.into() || column >= u8::MAX.into() {
+                    return Self::default();
+                }
+
+                match char {
+                    '#' => cells.push((row as i16, column as i16)),
+                    '.' => {}
+                    _ => return Self::default(),
+                }
+            }
+        }
+        Self::from_cells(cells)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Height and width of the bounding box
+    fn size(&self) -> (u8, u8) {
+        let height = self.0.iter().map(|&(row, _)| row + 1).max().unwrap_or(0);
+        let width = self
+            .0
+            .iter()
+            .map(|&(_, column)| column + 1)
+            .max()
+            .unwrap_or(0);
+        (height, width)
+    }
+
+    /// Straight and without gaps
+    pub fn is_line(&self) -> bool {
+        let (height, width) = self.size();
+        (height == 1 || width == 1) && self.len() == usize::from(height.max(width))
+    }
+
+    /// A single piece of cells joined by their sides
+    pub fn is_connected(&self) -> bool {
+        let Some(&first) = self.0.first() else {
+            return false;
+        };
+
+        let mut reached = vec![first];
+        let mut queue = vec![first];
+
+        while let Some((row, column)) = queue.pop() {
+            for &cell in &self.0 {
+                if !reached.contains(&cell) && row.abs_diff(cell.0) + column.abs_diff(cell.1) == 1 {
+                    reached.push(cell);
+                    queue.push(cell);
+                }
+            }
+        }
+        reached.len() == self.0.len()
+    }
+
+    /// Whether the shape can be laid on a board of this size one way or another
+    pub fn fits(&self, rows: u8, columns: u8) -> bool {
+        let (height, width) = self.size();
+        (height <= rows && width <= columns) || (width <= rows && height <= columns)
+    }
+
+    /// The shape mirrored left to right if `flipped`, then turned clockwise
+    pub fn oriented(&self, orientation: Orientation, flipped: bool) -> Self {
+        let mut cells: Vec<(i16, i16)> = self
+            .0
+            .iter()
+            .map(|&(row, column)| (row.into(), column.into()))
+            .collect();
+
+        if flipped {
+            for cell in &mut cells {
+                cell.1 = -cell.1;
+            }
+        }
+
+        for _ in 0..orientation.turns() {
+            for cell in &mut cells {
+                *cell = (cell.1, -cell.0);
+            }
+        }
+        Self::from_cells(cells)
+    }
+
+    /// Every distinct way the shape can lie on the board
+    pub fn variants(&self) -> Vec<Self> {
+        let mut variants: Vec<Self> = Vec::new();
+
+        for flipped in [false, true] {
+            for orientation in Orientation::ALL {
+                let variant = self.oriented(orientation, flipped);
+                if !variants.contains(&variant) {
+                    variants.push(variant);
+                }
+            }
+        }
+        variants
+    }
+
+    /// Whether the mirror image can't be had just by turning the shape
+    pub fn is_chiral(&self) -> bool {
+        let mirrored = self.oriented(Orientation::default(), true);
+
+        Orientation::ALL
+            .into_iter()
+            .all(|orientation| self.oriented(orientation, false) != mirrored)
+    }
+
+    /// Cells on the board, with the first cell of the shape at `start`
+    pub fn points(&self, start: Point) -> Option<Vec<Point>> {
+        let &(top, left) = self.0.first()?;
+
+        self.0
+            .iter()
+            .map(|&(row, column)| {
+                start.try_add_delta(row as isize - top as isize, column as isize - left as isize)
+            })
+            .collect()
+    }
+
+    /// Longest straight run of cells along a row or a column
+    pub fn longest_run(&self) -> u8 {
+        let run = |(row, column): (u8, u8), (dx, dy): (u8, u8)| {
+            (0..)
+                .take_while(|&i| {
+                    self.0
+                        .contains(&(row.wrapping_add(i * dx), column.wrapping_add(i * dy)))
+                })
+                .count() as u8
+        };
+
+        self.0
+            .iter()
+            .flat_map(|&cell| [run(cell, (0, 1)), run(cell, (1, 0))])
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Cells taken when every cell grows into a 2x2 block towards the bottom right.
+    // Ships that don't touch never share any of them.
+    pub(super) fn halo_area(&self) -> usize {
+        let mut cells: Vec<(u8, u8)> = self
+            .0
+            .iter()
+            .flat_map(|&(row, column)| {
+                [(0, 0), (0, 1), (1, 0), (1, 1)].map(|(dx, dy)| (row + dx, column + dy))
+            })
+            .collect();
+        cells.sort();
+        cells.dedup();
+        cells.len()
+    }
+
+    // Fewest blocks of the board's 2x2 grid the shape covers, however it's laid
+    pub(super) fn blocks(&self) -> usize {
+        let mut fewest = usize::MAX;
+
+        for variant in self.variants() {
+            for (dx, dy) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+                let mut blocks: Vec<(u8, u8)> = variant
+                    .0
+                    .iter()
+                    .map(|&(row, column)| ((row + dx) / 2, (column + dy) / 2))
+                    .collect();
+                blocks.sort();
+                blocks.dedup();
+                fewest = fewest.min(blocks.len());
+            }
+        }
+        fewest
+    }
+
+    /// Rows of the bounding box, marking where the cells are
+    pub fn rows(&self) -> Vec<Vec<bool>> {
+        let (height, width) = self.size();
+
+        (0..height)
+            .map(|row| {
+                (0..width)
+                    .map(|column| self.0.contains(&(row, column)))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl Display for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rows: Vec<String> = self
+            .rows()
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|cell| if cell { '#' } else { '.' })
+                    .collect()
+            })
+            .collect();
+
+        write!(f, "{}", rows.join("/"))
+    }
+}
+
+impl From<String> for Shape {
+    fn from(value: String) -> Self {
+        Self::parse(&value)
+    }
+}
+
+impl From<Shape> for String {
+    fn from(value: Shape) -> Self {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::ShipDefinition;
+
+    fn shape(s: &str) -> Shape {
+        Shape::from(s.to_string())
+    }
+
+    #[test]
+    fn normalizes() {
+        assert_eq!(shape("..../..#./..##"), shape("#./##"));
+        assert_eq!(shape(" #. / ## ").to_string(), "#./##");
+        assert_eq!(shape("##/##/##").len(), 6);
+        assert!(shape("#x").is_empty());
+        assert!(shape("####").is_line());
+        assert!(!shape("#./##").is_line());
+    }
+
+    #[test]
+    fn rotates_clockwise() {
+        let l = shape("#./#./##");
+
+        assert_eq!(l.oriented(Orientation::Down, false).to_string(), "###/#..");
+        assert_eq!(l.oriented(Orientation::Left, false).to_string(), "##/.#/.#");
+        assert_eq!(l.oriented(Orientation::Up, false).to_string(), "..#/###");
+        assert_eq!(l.oriented(Orientation::Right, false), l);
+    }
+
+    #[test]
+    fn reflects() {
+        let l = shape("#./#./##");
+
+        assert_eq!(l.oriented(Orientation::Right, true).to_string(), ".#/.#/##");
+        assert_eq!(l.oriented(Orientation::Down, true).to_string(), "#../###");
+        assert!(l.is_chiral());
+        assert!(!shape("###/.#.").is_chiral());
+    }
+
+    #[test]
+    fn counts_variants() {
+        assert_eq!(shape("#./#./##").variants().len(), 8);
+        assert_eq!(shape("###/.#.").variants().len(), 4);
+        assert_eq!(shape("###").variants().len(), 2);
+        assert_eq!(shape("##/##").variants().len(), 1);
+    }
+
+    #[test]
+    fn ship_takes_its_shape() {
+        let ship = ShipDefinition::new("Г", 1, 1)
+            .with_shape(shape("#./##"))
+            .unwrap();
+        assert_eq!(ship.length(), 3);
+        assert!(ship.custom_shape().is_some());
+
+        let line = ShipDefinition::new("Линкор", 1, 1)
+            .with_shape(shape("####"))
+            .unwrap();
+        assert_eq!(line.length(), 4);
+        assert!(line.custom_shape().is_none());
+
+        // Parsing caps the rows and columns, but not the cells
+        let block = vec!["#".repeat(20); 20].join("/");
+        let huge = ShipDefinition::new("Остров", 1, 1).with_shape(shape(&block));
+        assert!(huge.is_err());
+    }
+}
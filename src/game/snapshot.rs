@@ -0,0 +1,356 @@
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use time::{Date, OffsetDateTime};
+
+use crate::game::{
+    Alphabet, Arsenal, Board, BoardBuilder, Bounds, CellContent, Difficulty, Fleet, MAX_SIDE,
+    Orientation, Placement, Point, Rules, Seat, Seed, Shape, ShipDefinition,
+    battle::{Ending, Move},
+    weapons::Ping,
+};
+
+/// Bumped whenever the snapshot format changes in an incompatible way
+const SNAPSHOT_VERSION: u32 = 1;
+
+fn snapshot_version() -> u32 {
+    SNAPSHOT_VERSION
+}
+
+/// Plain data version of a [`Board`].
+/// Ship health and counters are not stored, they follow from the shots.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BoardSnapshot {
+    #[serde(default = "snapshot_version")]
+    version: u32,
+    bounds: Bounds,
+    fleet: Vec<ShipDefinition>,
+    ships: Vec<ShipPosition>,
+    /// Cells the opponent can see, including ones exposed around sunk ships
+    exposed: Vec<Point>,
+    #[serde(default)]
+    shots: Vec<Point>,
+    #[serde(default)]
+    pings: Vec<Ping>,
+    #[serde(default)]
+    alphabet: Alphabet,
+    #[serde(default)]
+    ships_touch: bool,
+    #[serde(default)]
+    islands: Vec<Point>,
+    #[serde(default)]
+    mines: Vec<Point>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ShipPosition {
+    /// Index into the fleet definitions
+    kind: usize,
+    cells: Vec<Point>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum FleetSnapshot {
+    Placing {
+        board: BoardSnapshot,
+        selected: usize,
+        orientation: Orientation,
+        #[serde(default)]
+        flipped: bool,
+    },
+    Ready(BoardSnapshot),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum ControlSnapshot {
+    Human,
+    Computer(Difficulty),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SideSnapshot {
+    pub(super) fleet: FleetSnapshot,
+    pub(super) control: ControlSnapshot,
+    /// Special weapons left
+    #[serde(default)]
+    pub(super) ammo: Arsenal,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GameSnapshot {
+    pub(super) sides: [SideSnapshot; 2],
+    pub(super) turn: Seat,
+    pub(super) winner: Option<Seat>,
+    #[serde(default)]
+    pub(super) seed: Option<Seed>,
+    #[serde(default)]
+    pub(super) daily: Option<Date>,
+    #[serde(default)]
+    pub(super) rules: Rules,
+    #[serde(default)]
+    pub(super) skips: [usize; 2],
+    /// Every shot in order, also handy for looking through an exported game
+    #[serde(default)]
+    pub(super) log: Vec<Move>,
+    #[serde(default)]
+    pub(super) practice: bool,
+    #[serde(default)]
+    pub(super) ending: Ending,
+    #[serde(default)]
+    pub(super) started: Option<OffsetDateTime>,
+    #[serde(default)]
+    pub(super) finished: Option<OffsetDateTime>,
+}
+
+impl GameSnapshot {
+    pub fn daily(&self) -> Option<Date> {
+        self.daily
+    }
+}
+
+impl Board {
+    pub fn snapshot(&self) -> BoardSnapshot {
+        let fleet = self
+            .ship_counters
+            .iter()
+            .map(|counter| counter.definition())
+            .collect();
+
+        let ships = self
+            .ships
+            .iter()
+            .map(|ship| ShipPosition {
+                kind: ship.kind,
+                cells: ship.cells.clone(),
+            })
+            .collect();
+
+        let terrain = |content: fn(&CellContent) -> bool| {
+            self.cells()
+                .filter(|(_, cell)| content(&cell.content))
+                .map(|(point, _)| point)
+                .collect()
+        };
+
+        let exposed = self
+            .cells()
+            .filter(|(_, cell)| cell.exposed)
+            .map(|(point, _)| point)
+            .collect();
+
+        BoardSnapshot {
+            version: SNAPSHOT_VERSION,
+            bounds: self.bounds,
+            fleet,
+            ships,
+            exposed,
+            shots: self.shots.clone(),
+            pings: self.pings.clone(),
+            alphabet: self.alphabet,
+            ships_touch: self.ships_touch,
+            islands: terrain(|content| matches!(content, CellContent::Island)),
+            mines: terrain(|content| matches!(content, CellContent::Mine)),
+        }
+    }
+
+    pub fn restore(snapshot: &BoardSnapshot) -> Result<Self> {
+        let builder = BoardBuilder::restore(snapshot)?;
+        if !builder.is_complete() {
+            bail!("Not every ship is placed");
+        }
+        let mut board = builder.inner;
+
+        board.pings = snapshot.pings.clone();
+
+        // Replaying the shots sinks ships and updates counters on the way
+        for &point in &snapshot.shots {
+            board
+                .hit(point)
+                .map_err(|_| anyhow!("Invalid shot at {point}"))?;
+        }
+
+        // Snapshots without history only list what was exposed
+        for &point in &snapshot.exposed {
+            let cell = board
+                .get_cell(point)
+                .ok_or(anyhow!("Exposed cell {point} is out of bounds"))?;
+
+            if !cell.exposed {
+                board
+                    .expose(point)
+                    .map_err(|_| anyhow!("Invalid exposed cell {point}"))?;
+            }
+        }
+
+        Ok(board)
+    }
+}
+
+impl BoardBuilder {
+    pub(super) fn restore(snapshot: &BoardSnapshot) -> Result<Self> {
+        if snapshot.version > SNAPSHOT_VERSION {
+            bail!("Unsupported snapshot version {}", snapshot.version);
+        }
+
+        let Bounds {
+            x: rows,
+            y: columns,
+        } = snapshot.bounds;
+        if rows == 0 || columns == 0 || rows > MAX_SIDE || columns > MAX_SIDE {
+            bail!("Invalid board size {rows}x{columns}");
+        }
+
+        for ship in &snapshot.fleet {
+            let shape = ship.shape();
+            if shape.len() != usize::from(ship.length()) || !shape.is_connected() {
+                bail!("Invalid shape of ship {}", ship.name());
+            }
+        }
+
+        let mut builder = Self::new(snapshot.bounds)
+            .alphabet(snapshot.alphabet)
+            .ships_touch(snapshot.ships_touch)
+            .fleet(&snapshot.fleet);
+
+        for (points, content) in [
+            (&snapshot.islands, CellContent::Island),
+            (&snapshot.mines, CellContent::Mine),
+        ] {
+            for &point in points {
+                let cell = builder
+                    .inner
+                    .get_cell_mut(point)
+                    .ok_or(anyhow!("Terrain cell {point} is out of bounds"))?;
+
+                cell.content = content;
+                if let CellContent::Island = content {
+                    cell.expose();
+                }
+            }
+        }
+
+        for ship in &snapshot.ships {
+            if ship.kind >= snapshot.fleet.len() {
+                bail!("Unknown ship kind {}", ship.kind);
+            }
+
+            // Also rules out repeated cells, which make a smaller shape
+            let shape = Shape::of_points(&ship.cells);
+            if !builder.footprints[ship.kind].variants.contains(&shape) {
+                bail!(
+                    "Ship cells don't match the shape of {}",
+                    snapshot.fleet[ship.kind].name()
+                );
+            }
+
+            builder
+                .add_ship(ship.kind, ship.cells.clone())
+                .map_err(|e| anyhow!("Invalid ship position: {e}"))?;
+        }
+
+        Ok(builder)
+    }
+}
+
+impl Fleet {
+    pub fn snapshot(&self) -> FleetSnapshot {
+        match self {
+            Self::Placing(placement) => FleetSnapshot::Placing {
+                board: placement.builder.inner.snapshot(),
+                selected: placement.selected,
+                orientation: placement.orientation,
+                flipped: placement.flipped,
+            },
+            Self::Ready(board) => FleetSnapshot::Ready(board.snapshot()),
+        }
+    }
+
+    pub fn restore(snapshot: &FleetSnapshot) -> Result<Self> {
+        Ok(match snapshot {
+            FleetSnapshot::Placing {
+                board,
+                selected,
+                orientation,
+                flipped,
+            } => {
+                let mut placement = Placement::new(BoardBuilder::restore(board)?);
+                placement.select(*selected)?;
+                placement.orientation = *orientation;
+                placement.flipped = *flipped;
+                Self::Placing(placement)
+            }
+            FleetSnapshot::Ready(board) => {
+                Self::Ready(Board::restore(board).context("Failed to restore fleet")?)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::seeded_rng;
+
+    fn board() -> Board {
+        let mut rng = seeded_rng(7);
+        let mut board = BoardBuilder::square(10)
+            .terrain(3, 2, &mut rng)
+            .random(&ShipDefinition::classic(), &mut rng)
+            .expect("classic fleet fits");
+
+        for i in (0..100).step_by(7) {
+            let point = Point::from_index(i / 10, i % 10);
+            if !board.get_cell(point).unwrap().exposed {
+                assert!(board.hit(point).is_ok());
+            }
+        }
+        board
+    }
+
+    fn json(snapshot: &BoardSnapshot) -> String {
+        serde_json::to_string(snapshot).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let snapshot = board().snapshot();
+        let restored = Board::restore(&snapshot).expect("own snapshot restores");
+
+        assert_eq!(json(&restored.snapshot()), json(&snapshot));
+    }
+
+    #[test]
+    fn rejects_bad_bounds() {
+        for bounds in [Point::new(3, 0), Point::new(0, 3), Point::new(10, 255)] {
+            let mut snapshot = board().snapshot();
+            snapshot.bounds = bounds;
+            assert!(Board::restore(&snapshot).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_bad_ships() {
+        let mut overlapping = board().snapshot();
+        overlapping.ships[1].cells = overlapping.ships[0].cells.clone();
+
+        let mut outside = board().snapshot();
+        outside.ships[0].cells = outside.ships[0]
+            .cells
+            .iter()
+            .map(|point| Point::new(point.x, point.y + 10))
+            .collect();
+
+        let mut repeated = board().snapshot();
+        let first = repeated.ships[0].cells[0];
+        repeated.ships[0].cells = vec![first; 4];
+
+        let mut missing = board().snapshot();
+        missing.ships.pop();
+
+        let mut unknown = board().snapshot();
+        unknown.ships[0].kind = 10;
+
+        for snapshot in [overlapping, outside, repeated, missing, unknown] {
+            assert!(Board::restore(&snapshot).is_err());
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+
+use crate::game::{Game, Seat, ShotResult};
+
+/// How a player did in a game
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Stats {
+    pub shots: usize,
+    pub hits: usize,
+    /// Ships of the opponent sunk
+    pub sunk: usize,
+    /// Most hits in a row
+    pub streak: usize,
+}
+
+impl Stats {
+    /// Share of the shots that hit, in percent
+    pub fn accuracy(&self) -> usize {
+        (self.hits * 100).checked_div(self.shots).unwrap_or(0)
+    }
+
+    /// Shots it took on average to sink a ship, none if nothing sank
+    pub fn shots_per_sunk(&self) -> Option<f64> {
+        (self.sunk > 0).then(|| self.shots as f64 / self.sunk as f64)
+    }
+}
+
+/// Stats of a finished game, kept for its final screen
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Summary {
+    /// Stats of the host and the guest
+    pub players: [Stats; 2],
+    /// Time from setting the game up to its end
+    pub duration: Duration,
+}
+
+impl Summary {
+    pub fn of(&self, seat: Seat) -> &Stats {
+        &self.players[seat.index()]
+    }
+}
+
+impl Game {
+    pub fn stats(&self, seat: Seat) -> Stats {
+        let mut stats = Stats::default();
+        let mut streak = 0;
+
+        for entry in self.log().iter().filter(|entry| entry.seat == seat) {
+            stats.shots += 1;
+
+            match entry.shot.result {
+                ShotResult::Miss => streak = 0,
+                result => {
+                    stats.hits += 1;
+                    stats.sunk += usize::from(result == ShotResult::Sunk);
+                    streak += 1;
+                    stats.streak = stats.streak.max(streak);
+                }
+            }
+        }
+
+        stats
+    }
+
+    /// Stats of both players, with the game counted from `created`
+    pub fn summary(&self, created: OffsetDateTime) -> Summary {
+        let end = self.finished().unwrap_or_else(OffsetDateTime::now_utc);
+
+        Summary {
+            players: [self.stats(Seat::Host), self.stats(Seat::Guest)],
+            duration: (end - created).max(Duration::ZERO),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{
+        BoardBuilder, Difficulty, Fleet, Opponent, Point, ShipDefinition, seeded_rng,
+    };
+
+    #[test]
+    fn stats_follow_the_shots() {
+        let board = |seed| {
+            BoardBuilder::square(10)
+                .random(&ShipDefinition::classic(), &mut seeded_rng(seed))
+                .expect("classic fleet fits")
+        };
+        let mut game = Game::solo(
+            Fleet::Ready(board(1)),
+            board(2),
+            Opponent::new(Difficulty::default()),
+        )
+        .with_practice();
+
+        let Fleet::Ready(target) = game.fleet(Seat::Guest) else {
+            panic!("the computer's fleet is placed");
+        };
+        let ship = |kind| target.ships.iter().find(|ship| ship.kind == kind).unwrap();
+        let (destroyer, torpedo) = (ship(2), ship(3));
+        let water: Vec<Point> = target
+            .cells()
+            .filter(|(point, cell)| {
+                !cell.contains_ship() && !destroyer.nearby_cells.contains(point)
+            })
+            .map(|(point, _)| point)
+            .take(2)
+            .collect();
+
+        let script = [
+            water[0],
+            destroyer.cells[0],
+            destroyer.cells[1],
+            water[1],
+            torpedo.cells[0],
+        ];
+        for point in script {
+            game.fire(Seat::Host, point).unwrap();
+        }
+
+        let stats = game.stats(Seat::Host);
+        assert_eq!(
+            (stats.shots, stats.hits, stats.sunk, stats.streak),
+            (5, 3, 2, 2)
+        );
+        assert_eq!(stats.accuracy(), 60);
+        assert_eq!(stats.shots_per_sunk(), Some(2.5));
+
+        let none = Stats::default();
+        assert_eq!(none.accuracy(), 0);
+        assert_eq!(none.shots_per_sunk(), None);
+    }
+}
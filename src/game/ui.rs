@@ -1,24 +1,101 @@
+use std::fmt::Display;
+
 use maud::{Markup, PreEscaped, html};
+use serde::{Deserialize, Serialize};
+use time::Duration;
+use uuid::Uuid;
 
-use crate::game::{Board, CellRef, CellState, HitDisplayDiff, Point, ShipCounter};
+use crate::game::{
+    Board, CellContent, CellState, Difficulty, FitError, Fleet, FleetError, FleetReport, Game,
+    HitDisplayDiff, MAX_SIDE, Outcome, Outline, Placement, Point, Replay, Seat, Seed, Shape,
+    ShapeTooLarge, ShipAddError, ShipCounter, ShotResult, Summary, Weapon,
+    battle::{Aim, Arms, Ending, Move, Turn, Undo},
+};
 
 // TODO: some stuff can be much better if we replace maud with a typed html engine that understands htmx
 // Unfortunately, no such thing exists from my knowledge
 
-fn int_to_letter(value: usize) -> char {
-    // NOTE: Ё :(
-    const ALPHABET: &str = "АБВГДЕЖЗИКЛМНОПРСТУФХЦЧШЩЭЮЯ";
-    ALPHABET.chars().nth(value).unwrap_or('~')
+/// Letters used to name board columns
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Alphabet {
+    #[default]
+    Cyrillic,
+    Latin,
 }
 
-enum RenderMode {
+impl Alphabet {
+    pub const ALL: [Self; 2] = [Self::Cyrillic, Self::Latin];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Cyrillic => "cyrillic",
+            Self::Latin => "latin",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Cyrillic => "А, Б, В…",
+            Self::Latin => "A, B, C…",
+        }
+    }
+
+    fn letters(self) -> &'static str {
+        match self {
+            // NOTE: Ё, Й, Ъ, Ы and Ь are left out, as on paper boards
+            Self::Cyrillic => "АБВГДЕЖЗИКЛМНОПРСТУФХЦЧШЩЭЮЯ",
+            Self::Latin => "ABCDEFGHIJKLMNOPQRSTUVWXYZ",
+        }
+    }
+
+    // Spreadsheet-style, the last single letter is followed by АА, АБ…
+    fn column(self, mut index: usize) -> String {
+        let letters: Vec<char> = self.letters().chars().collect();
+        let mut label = Vec::new();
+
+        loop {
+            label.push(letters[index % letters.len()]);
+            if index < letters.len() {
+                break;
+            }
+            index = index / letters.len() - 1;
+        }
+
+        label.iter().rev().collect()
+    }
+}
+
+pub enum RenderMode {
     Paint,
     Update,
 }
 
+/// Which of the two boards on screen is being drawn
+#[derive(Clone, Copy)]
+pub enum Side {
+    /// Opponent's board, hidden from the player
+    Target,
+    /// Player's own fleet
+    Fleet,
+    /// Opponent's board with every ship shown, once the game is over
+    Review,
+}
+
+impl Side {
+    // Target cell ids are parsed back from HX-Target, so they stay unprefixed
+    fn cell_id(self, point: Point) -> String {
+        match self {
+            Self::Target => point.to_string(),
+            Self::Fleet => format!("fleet-{point}"),
+            Self::Review => format!("review-{point}"),
+        }
+    }
+}
+
 impl RenderMode {
     // TODO: consider removing or rewriting as a macro
-    fn element(&self, id: String, class: &'static str, html: Markup) -> Markup {
+    fn element(&self, id: String, class: &str, html: Markup) -> Markup {
         html!({
             @if matches!(self, Self::Update) {
                 div id=(id) class=(PreEscaped(class)) hx-swap-oob="true" {(html)}
@@ -29,40 +106,289 @@ impl RenderMode {
     }
 }
 
+// Game screens keep a live connection for updates made by the opponent
+fn screen(content: Markup) -> Markup {
+    html! {
+        #screen data-events="/game/events" {
+            #display .game {
+                (content)
+            }
+        }
+    }
+}
+
+fn render_status(status: &str, mode: RenderMode) -> Markup {
+    mode.element("status".into(), "", html!({ (status) }))
+}
+
+impl Point {
+    // Human-readable coordinates, matching the board axis labels
+    fn label(&self, alphabet: Alphabet) -> String {
+        format!("{}{}", alphabet.column(self.y.into()), self.x + 1)
+    }
+}
+
 impl Board {
-    pub async fn render(&self) -> Markup {
+    fn render_grid<F>(&self, id: &str, render_cell: F) -> Markup
+    where
+        F: Fn(Point, &CellState) -> Markup,
+    {
         html! {
-            #screen {
-            #display .game {
-                #stats-container {
-                    @for counter in &self.ship_counters {
-                        (counter.read().await.render(RenderMode::Paint))
-                    }
+            .board id=(id) {
+                style {
+                    (format!(
+                        "#{id} {{ grid-template-columns: repeat({}, 1fr); aspect-ratio: {} / {} }}",
+                        self.bounds.y + 1,
+                        self.bounds.y + 1,
+                        self.bounds.x + 1
+                    ))
                 }
 
-                #board {
-                    style {
-                        (format!(
-                            "#board {{ grid-template-columns: repeat({}, 1fr) }}",
-                            self.state.len() + 1
-                        ))
-                    }
+                div .cell .ui { };
+                @for i in (0..self.bounds.y) {
+                    div .cell .ui {(self.alphabet.column(i.into()))}
+                }
 
-                    div .cell .ui { };
-                    @for i in (0..self.state.len()) {
-                        div .cell .ui {(int_to_letter(i))}
+                @for (x, row) in self.rows().enumerate() {
+                    div .cell .ui {(x+1)}
+                    @for (y, cell) in row.iter().enumerate() {
+                        @let point = Point::from_index(x,y);
+                        (render_cell(point, cell))
                     }
+                }
+            }
+        }
+    }
+
+    // Reminds players of a rule that changes what a sunk ship gives away
+    fn render_variant(&self) -> Markup {
+        html! {
+            @if self.ships_touch {
+                .variant title="Клетки вокруг потопленных кораблей не открываются" {
+                    "Корабли могут касаться"
+                }
+            }
+        }
+    }
+
+    fn render_fleet(&self) -> Markup {
+        self.render_grid("fleet-board", |point, cell| {
+            cell.render(
+                point,
+                Side::Fleet,
+                self.sunk_outline(point),
+                RenderMode::Paint,
+            )
+        })
+    }
+
+    pub fn render(
+        &self,
+        fleet: &Board,
+        status: Option<&str>,
+        seed: Option<Seed>,
+        aim: Option<Aim>,
+        arms: Option<Arms>,
+        extras: Markup,
+    ) -> Markup {
+        let aimed = aim.as_ref().map_or(&[][..], |aim| aim.cells);
+
+        screen(html! {
+            #stats-container {
+                // Kept on screen even when empty, so moves can report into it
+                (render_status(status.unwrap_or_default(), RenderMode::Paint))
+                @if let Some(aim) = &aim {
+                    (aim.render(RenderMode::Paint))
+                }
+                @if let Some(seed) = seed {
+                    #seed title="Номер поля, по нему можно сыграть ещё раз" {"Поле " (seed)}
+                }
+                (self.render_variant())
+                @for counter in &self.ship_counters {
+                    (counter.render(RenderMode::Paint))
+                }
+                @if let Some(arms) = &arms {
+                    (arms.render(RenderMode::Paint))
+                }
+                // Move list and whatever else the game adds below the counters
+                (extras)
+            }
+
+            (self.render_grid("board", |point, cell| match cell.exposed {
+                false => render_target(
+                    point,
+                    aimed.contains(&point),
+                    self.ping_at(point),
+                    RenderMode::Paint,
+                ),
+                true => {
+                    cell.render(point, Side::Target, self.sunk_outline(point), RenderMode::Paint)
+                }
+            }))
+
+            (fleet.render_fleet())
+        })
+    }
+}
+
+impl Difficulty {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Easy => "Легко",
+            Self::Medium => "Средне",
+            Self::Hard => "Сложно",
+        }
+    }
+}
+
+impl Display for ShipAddError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Collision { point, alphabet } => {
+                write!(
+                    f,
+                    "Слишком близко к другому кораблю ({})",
+                    point.label(*alphabet)
+                )
+            }
+            Self::Blocked { point, alphabet } => {
+                write!(
+                    f,
+                    "Клетка {} занята островом или миной",
+                    point.label(*alphabet)
+                )
+            }
+            Self::OutOfBounds => write!(f, "Корабль не помещается на поле"),
+            Self::NoneRemaining => write!(f, "Все корабли этого типа уже расставлены"),
+            Self::InternalError(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Display for FitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoRoom { ship } => write!(f, "Не хватает места для корабля «{}»", ship.name),
+            Self::GaveUp { ship } => write!(
+                f,
+                "Проверка не нашла места для корабля «{}», но перебрала не все варианты: флот может и поместиться. Уменьшите флот или увеличьте поле",
+                ship.name
+            ),
+        }
+    }
+}
+
+impl Display for ShapeTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "В форме корабля больше {} клеток", u8::MAX)
+    }
+}
+
+impl Display for FleetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EmptyBoard => write!(f, "Поле не может быть пустым"),
+            Self::BoardTooLarge => write!(f, "Поле больше {MAX_SIDE}×{MAX_SIDE} не поддерживается"),
+            Self::EmptyFleet => write!(f, "Добавьте хотя бы один корабль"),
+            Self::UnnamedShip => write!(f, "У каждого корабля должно быть название"),
+            Self::DuplicateName { ship } => {
+                write!(f, "Название «{}» встречается несколько раз", ship.name)
+            }
+            Self::ZeroLength { ship } => write!(f, "У корабля «{}» нулевая длина", ship.name),
+            Self::TooLong { ship } => write!(f, "Корабль «{}» длиннее поля", ship.name),
+            Self::BadShape { ship } => write!(
+                f,
+                "Форма корабля «{}» должна быть одним куском из клеток «#»",
+                ship.name
+            ),
+            Self::TooDense { cells, max_cells } => write!(
+                f,
+                "Корабли занимают {cells} клеток, а на этом поле помещается не больше {max_cells}"
+            ),
+            Self::NoRoom(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+/// Result of checking a custom fleet, shown next to the setup form
+pub fn render_fleet_check(result: &Result<FleetReport, FleetError>) -> Markup {
+    html!({
+        @match result {
+            Ok(report) => #fleet-check .valid {
+                (format!(
+                    "Флот помещается: кораблей — {}, клеток — {}, занято {:.0}% поля (не больше {:.0}%)",
+                    report.ships,
+                    report.cells,
+                    report.density * 100.0,
+                    report.max_density * 100.0,
+                ))
+            },
+            Err(error) => #fleet-check .invalid {(error)},
+        }
+    })
+}
 
-                    @for (x, row) in self.state.iter().enumerate() {
-                        div .cell .ui {(x+1)}
-                        @for (y, cell) in row.iter().enumerate() {
-                            @let point = Point::from_index(x,y);
-                            (cell.read().await.render(point, RenderMode::Paint))
+impl Placement {
+    pub fn render(&self, status: Option<String>) -> Markup {
+        let builder = &self.builder;
+
+        let shape = builder.fleet[self.selected].shape();
+
+        let orientation = match (shape.is_line(), self.orientation.is_horizontal()) {
+            (false, _) => "Повернуть",
+            (true, true) => "Горизонтально",
+            (true, false) => "Вертикально",
+        };
+
+        screen(html! {
+            #stats-container {
+                @for (kind, ship) in builder.fleet.iter().enumerate() {
+                    @let remaining = builder.remaining(kind);
+                    .ship-counter
+                        .selected[kind == self.selected]
+                        .defeated[remaining == 0]
+                        hx-post=(format!("/game/placement/select/{kind}"))
+                        hx-target="#screen"
+                    {
+                        .cnt-name {(ship.name)}
+                        .cnt-row {
+                            .cnt-remaining {(remaining)} "/" .cnt-total {(ship.count)}
+                        }
+                        @if let Some(custom) = ship.custom_shape() {
+                            @match kind == self.selected {
+                                true => (render_shape(&custom.oriented(self.orientation, self.flipped))),
+                                false => (render_shape(custom)),
+                            }
                         }
                     }
                 }
-            }}
-        }
+
+                #placement-controls {
+                    .btn.control hx-post="/game/placement/rotate" hx-target="#screen"
+                        {(orientation)}
+                    @if shape.is_chiral() {
+                        .btn.control hx-post="/game/placement/flip" hx-target="#screen"
+                            {"Отразить"}
+                    }
+                    .btn.control hx-post="/game/placement/random" hx-target="#screen"
+                        {"Случайно"}
+                    .btn.control hx-post="/game/placement/clear" hx-target="#screen"
+                        {"Очистить"}
+                    @if builder.is_complete() {
+                        .btn.control hx-post="/game/placement/done" hx-target="#screen"
+                            {"В бой"}
+                    }
+                }
+
+                @if let Some(status) = status {
+                    #placement-status {(status)}
+                }
+
+                (builder.inner.render_variant())
+            }
+
+            (builder.inner.render_grid("board", |point, cell| cell.render_placement(point)))
+        })
     }
 }
 
@@ -75,63 +401,606 @@ impl ShipCounter {
         };
 
         mode.element(
-            self.name.clone(), // TODO: id independent of ship name
+            format!("ship-{}", self.kind),
             class,
             html!({
                 .cnt-name {(self.name)}
                 .cnt-row {
                     .cnt-remaining {(self.remaining)} "/" .cnt-total {(self.total)}
                 }
+                @if !self.shape.is_line() {
+                    (render_shape(&self.shape))
+                }
             }),
         )
     }
 }
 
+// Small picture of a ship that isn't a straight line
+fn render_shape(shape: &Shape) -> Markup {
+    let rows = shape.rows();
+    let columns = rows.first().map_or(0, Vec::len);
+
+    html!({
+        .cnt-shape style=(format!("grid-template-columns: repeat({columns}, 1fr)")) {
+            @for row in &rows {
+                @for &filled in row {
+                    .shape-cell.filled[filled] {}
+                }
+            }
+        }
+    })
+}
+
+impl Outline {
+    fn classes(self) -> String {
+        [
+            (self.top, " edge-top"),
+            (self.right, " edge-right"),
+            (self.bottom, " edge-bottom"),
+            (self.left, " edge-left"),
+        ]
+        .into_iter()
+        .filter(|&(edge, _)| edge)
+        .map(|(_, class)| class)
+        .collect()
+    }
+}
+
+// Hidden cell of the opponent's board, which can be shot at or marked for a salvo.
+// Cells the sonar went over tell whether it found anything there.
+fn render_target(point: Point, aimed: bool, ping: Option<bool>, mode: RenderMode) -> Markup {
+    let mut class = String::from("cell active");
+    if aimed {
+        class.push_str(" aimed");
+    }
+    match ping {
+        Some(true) => class.push_str(" pinged found"),
+        Some(false) => class.push_str(" pinged clear"),
+        None => {}
+    }
+    let oob = matches!(mode, RenderMode::Update).then_some("true");
+
+    html!({
+        div id=(Side::Target.cell_id(point)) class=(class) hx-patch="/game" hx-swap-oob=[oob] {}
+    })
+}
+
 impl CellState {
-    fn render(&self, point: Point, mode: RenderMode) -> Markup {
-        if self.exposed {
-            let class = match self.contains_ship() {
-                true => "cell ship",
-                false => "cell water",
-            };
-
-            mode.element(point.to_string(), class, PreEscaped("".into()))
-        } else {
-            html!({
-                div id=(point) class="cell active" hx-patch="game" {}
-            })
+    fn render(
+        &self,
+        point: Point,
+        side: Side,
+        outline: Option<Outline>,
+        mode: RenderMode,
+    ) -> Markup {
+        let id = side.cell_id(point);
+
+        let class = match (side, self.exposed, self.content) {
+            (_, _, CellContent::Island) => "cell island",
+            (_, true, CellContent::Mine) => "cell mine exposed",
+            (Side::Target, false, _) => return render_target(point, false, None, mode),
+            (Side::Fleet | Side::Review, false, CellContent::Mine) => "cell mine",
+            (Side::Fleet | Side::Review, false, CellContent::Ship(_)) => "cell ship",
+            (Side::Fleet | Side::Review, false, _) => "cell idle",
+            (_, true, CellContent::Ship(_)) => "cell ship hit",
+            (_, true, _) => "cell water",
+        };
+
+        // Sunk ships are outlined, so their shape stands out from the damaged ones
+        match outline {
+            Some(outline) => mode.element(
+                id,
+                &format!("cell ship sunk{}", outline.classes()),
+                PreEscaped("".into()),
+            ),
+            None => mode.element(id, class, PreEscaped("".into())),
         }
     }
 }
 
-impl CellRef {
-    async fn render(&self, mode: RenderMode) -> Markup {
-        self.accessor.read().await.render(self.point, mode)
+impl CellState {
+    fn render_placement(&self, point: Point) -> Markup {
+        match self.content {
+            CellContent::Ship(_) => html!({ div id=(point) class="cell ship" {} }),
+            CellContent::NearShip(_) => html!({ div id=(point) class="cell halo" {} }),
+            CellContent::Mine => html!({ div id=(point) class="cell mine" {} }),
+            CellContent::Island => html!({ div id=(point) class="cell island" {} }),
+            CellContent::Water => html!({
+                div id=(point) class="cell active" hx-patch="/game/placement" hx-target="#screen" {}
+            }),
+        }
     }
 }
 
 impl HitDisplayDiff {
-    pub async fn render(&self) -> Markup {
-        let mut result = self.cell.render(RenderMode::Paint).await.into_string();
+    pub fn render(&self, side: Side, mode: RenderMode) -> Markup {
+        let mut cells = self.cells.iter();
+
+        html!({
+            @if let Some((point, cell)) = cells.next() {
+                (cell.render(*point, side, self.outline(*point), mode))
+            }
+            // Cells of a sunk ship and the ones exposed around it
+            @for (point, cell) in cells {
+                (cell.render(*point, side, self.outline(*point), RenderMode::Update))
+            }
+            // Only the opponent's ships are counted on screen
+            @if let Side::Target = side {
+                @for counter in &self.sank_ships {
+                    (counter.render(RenderMode::Update))
+                }
+                @for &(point, found) in &self.pinged {
+                    (render_target(point, false, Some(found), RenderMode::Update))
+                }
+            }
+        })
+    }
+}
+
+impl Aim<'_> {
+    fn render(&self, mode: RenderMode) -> Markup {
+        mode.element(
+            "salvo".into(),
+            "",
+            html!({
+                .salvo-count {"Залп: " (self.cells.len()) " из " (self.shots)}
+                @if self.cells.len() == self.shots {
+                    .btn.control hx-post="/game/salvo" hx-swap="none" {"Огонь"}
+                }
+            }),
+        )
+    }
+}
+
+impl Weapon {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Sonar => "Сонар",
+            Self::Airstrike => "Авиаудар",
+            Self::Torpedo => "Торпеда",
+        }
+    }
+}
+
+impl Arms {
+    fn render(&self, mode: RenderMode) -> Markup {
+        mode.element(
+            "weapons".into(),
+            "",
+            html!({
+                @for weapon in Weapon::ALL {
+                    @let total = self.total.get(weapon);
+                    @let left = self.ammo.get(weapon);
+                    @if total > 0 {
+                        div
+                            .ship-counter.weapon
+                            .selected[self.armed == Some(weapon)]
+                            .defeated[left == 0]
+                            hx-post=(format!("/game/weapon/{}", weapon.id()))
+                            hx-swap="none"
+                        {
+                            .cnt-name {(weapon.label())}
+                            .cnt-row {
+                                .cnt-remaining {(left)} "/" .cnt-total {(total)}
+                            }
+                        }
+                    }
+                }
+            }),
+        )
+    }
+}
+
+impl ShotResult {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Miss => "Мимо",
+            Self::Hit => "Попал",
+            Self::Sunk => "Убил",
+        }
+    }
+}
+
+impl Ending {
+    // From the point of view of the winner or the loser
+    fn label(self, won: bool) -> &'static str {
+        match (self, won) {
+            (Self::Sunk, true) => "Все корабли соперника потоплены",
+            (Self::Sunk, false) => "Все ваши корабли потоплены",
+            (Self::OutOfShots, true) => "У соперника кончились выстрелы",
+            (Self::OutOfShots, false) => "У вас кончились выстрелы",
+            (Self::OutOfTime, _) => "Время вышло",
+            (Self::Surrender, true) => "Соперник сдался",
+            (Self::Surrender, false) => "Вы сдались",
+        }
+    }
+}
+
+// Minutes and seconds, like 12:05
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.whole_seconds().max(0);
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+impl Move {
+    // `names` are who made the move for each seat, the moves of `own` stand out
+    fn render(&self, number: usize, own: Seat, names: [&str; 2], alphabet: Alphabet) -> Markup {
+        html!({
+            .move.own[self.seat == own] id=(format!("move-{number}")) {
+                span .move-number {(number) "."}
+                " " (names[self.seat.index()]) ": " (self.shot.point.label(alphabet)) " — " (self.shot.result.label())
+                @if let Some(ship) = &self.shot.sunk {
+                    " «" (ship) "»"
+                }
+            }
+        })
+    }
+}
+
+impl Turn {
+    pub fn render(&self) -> Markup {
+        // A salvo is fired with a button, so no cell is waiting for the response
+        let mode = match self.detached {
+            true => RenderMode::Update,
+            false => RenderMode::Paint,
+        };
+
+        let next = match (self.passed, self.skipped) {
+            (true, _) => Some("Ход соперника"),
+            (false, true) => Some("Соперник пропускает ход"),
+            (false, false) => None,
+        };
+        let status: Vec<&str> = self
+            .player
+            .result()
+            .map(ShotResult::label)
+            .into_iter()
+            .chain(next)
+            .collect();
+
+        html!({
+            (self.player.render(Side::Target, mode))
+            @for opponent in &self.opponent {
+                (opponent.render(Side::Fleet, RenderMode::Update))
+            }
+            @for revealed in &self.revealed {
+                (revealed.render(Side::Target, RenderMode::Update))
+            }
+            @if !status.is_empty() {
+                (render_status(&status.join(". "), RenderMode::Update))
+            }
+        })
+    }
+
+    /// The same move as seen by the player who was shot at
+    pub fn render_opponent(&self) -> Markup {
+        html!({
+            (self.player.render(Side::Fleet, RenderMode::Update))
+            // Ships of the player who moved, given away by a mine
+            @for revealed in &self.opponent {
+                (revealed.render(Side::Target, RenderMode::Update))
+            }
+            @if self.passed {
+                (render_status("Ваш ход", RenderMode::Update))
+            } @else if self.skipped {
+                (render_status("Вы пропускаете ход", RenderMode::Update))
+            }
+        })
+    }
+}
+
+impl Game {
+    pub fn render(&self, seat: Seat) -> Markup {
+        match (self.fleet(seat), self.fleet(seat.other())) {
+            (Fleet::Placing(placement), _) => placement.render(None),
+            (Fleet::Ready(fleet), Fleet::Placing(_)) => screen(html! {
+                #stats-container {
+                    (render_status("Соперник расставляет корабли", RenderMode::Paint))
+                }
+                (fleet.render_fleet())
+            }),
+            (Fleet::Ready(fleet), Fleet::Ready(target)) => {
+                let status = match (self.is_online(), self.turn() == seat) {
+                    (false, _) => None,
+                    (true, true) => Some("Ваш ход"),
+                    (true, false) => Some("Ход соперника"),
+                };
+                // Everyone plays the same daily board, its seed would give it away
+                let seed = self.seed().filter(|_| self.daily().is_none());
+                target.render(
+                    fleet,
+                    status,
+                    seed,
+                    self.aim(seat),
+                    self.arms(seat),
+                    html!({
+                        (self.render_limits(seat, RenderMode::Paint))
+                        @if self.is_practice() {
+                            .btn.control #undo hx-post="/game/undo" hx-swap="none" {"Отменить ход"}
+                        }
+                        .btn.control #surrender
+                            hx-post="/game/surrender"
+                            hx-confirm="Сдаться и закончить игру?"
+                            hx-swap="none"
+                            {"Сдаться"}
+                        (self.render_moves(seat))
+                    }),
+                )
+            }
+        }
+    }
+
+    /// Salvo counter and button, if salvos are played
+    pub fn render_salvo(&self, seat: Seat, mode: RenderMode) -> Markup {
+        html!({
+            @if let Some(aim) = self.aim(seat) {
+                (aim.render(mode))
+            }
+        })
+    }
+
+    /// A cell that was just marked or unmarked for the salvo
+    pub fn render_aim(&self, seat: Seat, point: Point) -> Markup {
+        let aimed = self.aim(seat).is_some_and(|aim| aim.cells.contains(&point));
+        let ping = match self.fleet(seat.other()) {
+            Fleet::Ready(target) => target.ping_at(point),
+            Fleet::Placing(_) => None,
+        };
+
+        html!({
+            (render_target(point, aimed, ping, RenderMode::Paint))
+            (self.render_salvo(seat, RenderMode::Update))
+        })
+    }
+
+    // Column names of the boards, the same for both players
+    fn alphabet(&self) -> Alphabet {
+        match self.fleet(Seat::Host) {
+            Fleet::Ready(board) => board.alphabet,
+            Fleet::Placing(placement) => placement.builder.inner.alphabet,
+        }
+    }
+
+    fn names(seat: Seat) -> [&'static str; 2] {
+        match seat {
+            Seat::Host => ["Вы", "Соперник"],
+            Seat::Guest => ["Соперник", "Вы"],
+        }
+    }
 
-        if let Some(ship) = &self.sank_ship {
-            let ship = ship.read().await;
+    // Latest moves first, so new ones show up without scrolling
+    fn render_moves(&self, seat: Seat) -> Markup {
+        let alphabet = self.alphabet();
+        let names = Self::names(seat);
 
-            for cell in &ship.nearby_cells {
-                let rendered = cell.render(RenderMode::Update).await.into_string();
-                result.push_str(&rendered);
+        html!({
+            #move-list {
+                @for (i, entry) in self.log().iter().enumerate().rev() {
+                    (entry.render(i + 1, seat, names, alphabet))
+                }
             }
+        })
+    }
 
-            let counter = ship
-                .counter
-                .read()
-                .await
-                .render(RenderMode::Update)
-                .into_string();
+    /// Moves made on the turn, put on top of the move list
+    pub fn render_turn_moves(&self, seat: Seat, turn: &Turn) -> Markup {
+        let alphabet = self.alphabet();
+        let names = Self::names(seat);
 
-            result.push_str(&counter);
+        html!({
+            div hx-swap-oob="afterbegin:#move-list" {
+                @for (i, entry) in self.log().iter().enumerate().rev().take(turn.logged) {
+                    (entry.render(i + 1, seat, names, alphabet))
+                }
+            }
+        })
+    }
+
+    /// Shots and time left, if the game limits them.
+    /// Once the time is up the screen asks for the game again, which ends it.
+    pub fn render_limits(&self, seat: Seat, mode: RenderMode) -> Markup {
+        let shots = self.shots_left(seat);
+        let time = self.time_left();
+
+        html!({
+            @if shots.is_some() || time.is_some() {
+                (mode.element("limits".into(), "", html!({
+                    @if let Some(shots) = shots {
+                        div {"Выстрелов осталось: " (shots)}
+                    }
+                    @if let Some(time) = time {
+                        div
+                            hx-get="/game"
+                            hx-trigger=(format!("load delay:{}s", time.whole_seconds().max(0) + 1))
+                            hx-swap="none"
+                            {"Времени осталось: " (format_duration(time))}
+                    }
+                })))
+            }
+        })
+    }
+
+    /// Final screen with the outcome and how both players did.
+    /// The opponent's fleet is shown in full, unless it's the daily board everyone plays.
+    /// The match is given if the game may be watched again and downloaded.
+    pub fn render_over(&self, seat: Seat, summary: &Summary, replay: Option<Uuid>) -> Markup {
+        let won = self
+            .result(seat)
+            .is_some_and(|outcome| matches!(outcome, Outcome::Win));
+        let (own, other) = (summary.of(seat), summary.of(seat.other()));
+        let per_sunk = |shots: Option<f64>| match shots {
+            Some(shots) => format!("{shots:.1}"),
+            None => "—".to_string(),
+        };
+
+        html! {
+            #screen {
+                #display .game {
+                    #stats-container {
+                        #over-text {
+                            @if won { "Победа!" } @else { "Поражение" }
+                        }
+                        .over-reason {(self.ending().label(won))}
+                        table #over-stats {
+                            tr { th {} th {"Вы"} th {"Соперник"} }
+                            tr { td {"Выстрелов"} td {(own.shots)} td {(other.shots)} }
+                            tr { td {"Попаданий"} td {(own.hits)} td {(other.hits)} }
+                            tr { td {"Точность"} td {(own.accuracy()) "%"} td {(other.accuracy()) "%"} }
+                            tr { td {"Лучшая серия"} td {(own.streak)} td {(other.streak)} }
+                            tr { td {"Потоплено кораблей"} td {(own.sunk)} td {(other.sunk)} }
+                            tr {
+                                td {"Выстрелов на корабль"}
+                                td {(per_sunk(own.shots_per_sunk()))}
+                                td {(per_sunk(other.shots_per_sunk()))}
+                            }
+                        }
+                        .over-reason {"Игра длилась " (format_duration(summary.duration))}
+                        @if let Some(id) = replay {
+                            a #replay-open href=(format!("/replay/{id}?step=0")) {
+                                .btn.control {"Посмотреть повтор"}
+                            }
+                            a #export-open href=(format!("/game/over/{id}/export")) download {
+                                .btn.control {"Скачать игру"}
+                            }
+                        }
+                        a #exit-open href="/" {
+                            .btn.control {"Выход"}
+                        }
+                    }
+
+                    @if let (Fleet::Ready(target), None) = (self.fleet(seat.other()), self.daily()) {
+                        (target.render_grid("board", |point, cell| {
+                            cell.render(point, Side::Review, target.sunk_outline(point), RenderMode::Paint)
+                        }))
+                    }
+                    @if let Fleet::Ready(fleet) = self.fleet(seat) {
+                        (fleet.render_fleet())
+                    }
+                }
+            }
         }
+    }
+
+    /// Cells hidden again by an undo, with the moves taken off the list
+    pub fn render_undo(&self, undo: &Undo) -> Markup {
+        html!({
+            (undo.target.render(Side::Target, RenderMode::Update))
+            (undo.fleet.render(Side::Fleet, RenderMode::Update))
+            @for i in undo.moves.clone() {
+                div id=(format!("move-{}", i + 1)) hx-swap-oob="delete" {}
+            }
+            (render_status("Ход отменён", RenderMode::Update))
+        })
+    }
+
+    /// Special weapons left, if the game has any
+    pub fn render_arms(&self, seat: Seat, mode: RenderMode) -> Markup {
+        html!({
+            @if let Some(arms) = self.arms(seat) {
+                (arms.render(mode))
+            }
+        })
+    }
+}
+
+// Delay between moves while a replay plays by itself
+const AUTOPLAY_DELAY: &str = "1s";
+
+impl Replay {
+    /// Viewer at the current move, `url` is the replay's address without a query
+    pub fn render(&self, url: &str, autoplay: bool) -> Markup {
+        let [host, guest] = &self.boards;
+        let names = match self.solo {
+            true => ["Игрок", "Компьютер"],
+            false => ["Первый игрок", "Второй игрок"],
+        };
+
+        let link = |step: usize, autoplay: bool| match autoplay {
+            true => format!("{url}?step={step}&autoplay=true"),
+            false => format!("{url}?step={step}"),
+        };
+        let last = self.len();
+        let status = format!("Ход {} из {last}", self.step);
+
+        html! {
+            #screen {
+                #display .game {
+                    #stats-container {
+                        (render_status(&status, RenderMode::Paint))
+                        #replay-controls {
+                            (render_replay_button("⏮", "В начало", &link(0, false)))
+                            (render_replay_button("◀", "Назад", &link(self.step.saturating_sub(1), false)))
+                            @if autoplay {
+                                (render_replay_button("⏸", "Пауза", &link(self.step, false)))
+                            } @else {
+                                (render_replay_button("⏵", "Воспроизвести", &link(self.step, true)))
+                            }
+                            (render_replay_button("▶", "Вперёд", &link((self.step + 1).min(last), false)))
+                            (render_replay_button("⏭", "В конец", &link(last, false)))
+                        }
+                        a #replay-link href=(link(self.step, false)) {"Ссылка на этот ход"}
+                        @for counter in &guest.ship_counters {
+                            (counter.render(RenderMode::Paint))
+                        }
+                        #move-list {
+                            @for (i, entry) in self.log[..self.step].iter().enumerate().rev() {
+                                (entry.render(i + 1, Seat::Host, names, host.alphabet))
+                            }
+                        }
+                        // Asks for the next move on its own, until the game is over
+                        @if autoplay && self.step < last {
+                            div
+                                hx-get=(link(self.step + 1, true))
+                                hx-trigger=(format!("load delay:{AUTOPLAY_DELAY}"))
+                                hx-select="#screen"
+                                hx-target="#screen"
+                                hx-replace-url="true"
+                                {}
+                        }
+                    }
+
+                    (guest.render_grid("board", |point, cell| {
+                        cell.render(point, Side::Review, guest.sunk_outline(point), RenderMode::Paint)
+                    }))
+
+                    (host.render_fleet())
+                }
+            }
+        }
+    }
+}
+
+fn render_replay_button(icon: &str, title: &str, href: &str) -> Markup {
+    html!({
+        .btn.control
+            title=(title)
+            hx-get=(href)
+            hx-select="#screen"
+            hx-target="#screen"
+            hx-push-url="true"
+            {(icon)}
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn column_labels_wrap_to_two_letters() {
+        let cyrillic = |index| Alphabet::Cyrillic.column(index);
+        assert_eq!(cyrillic(0), "А");
+        assert_eq!(cyrillic(27), "Я");
+        assert_eq!(cyrillic(28), "АА");
+        assert_eq!(cyrillic(55), "АЯ");
+        assert_eq!(cyrillic(56), "БА");
 
-        PreEscaped(result)
+        let latin = |index| Alphabet::Latin.column(index);
+        assert_eq!(latin(0), "A");
+        assert_eq!(latin(25), "Z");
+        assert_eq!(latin(26), "AA");
+        assert_eq!(latin(51), "AZ");
+        assert_eq!(latin(52), "BA");
     }
 }
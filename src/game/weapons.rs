@@ -0,0 +1,250 @@
+use anyhow::anyhow;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::{Board, CellContent, HitDisplayDiff, Point},
+    utils::errors::{AnyhowWebExt, WebResult},
+};
+
+/// Special actions a player can take instead of a shot
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weapon {
+    /// Tells whether any ship hides in a 3x3 area, without opening it
+    Sonar,
+    /// Hits a segment of a row, see [`AIRSTRIKE_REACH`]
+    Airstrike,
+    /// Runs along a row to the right until it hits something
+    Torpedo,
+}
+
+// Cells hit by an airstrike on each side of the one it's aimed at
+const AIRSTRIKE_REACH: isize = 1;
+
+impl Weapon {
+    pub const ALL: [Self; 3] = [Self::Sonar, Self::Airstrike, Self::Torpedo];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Sonar => "sonar",
+            Self::Airstrike => "airstrike",
+            Self::Torpedo => "torpedo",
+        }
+    }
+}
+
+/// Number of uses of every weapon
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Arsenal {
+    #[serde(default)]
+    pub sonar: u8,
+    #[serde(default)]
+    pub airstrike: u8,
+    #[serde(default)]
+    pub torpedo: u8,
+}
+
+impl Arsenal {
+    pub fn get(&self, weapon: Weapon) -> u8 {
+        match weapon {
+            Weapon::Sonar => self.sonar,
+            Weapon::Airstrike => self.airstrike,
+            Weapon::Torpedo => self.torpedo,
+        }
+    }
+
+    fn get_mut(&mut self, weapon: Weapon) -> &mut u8 {
+        match weapon {
+            Weapon::Sonar => &mut self.sonar,
+            Weapon::Airstrike => &mut self.airstrike,
+            Weapon::Torpedo => &mut self.torpedo,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        Weapon::ALL.into_iter().all(|weapon| self.get(weapon) == 0)
+    }
+
+    // Uses up one charge, returns false if there was none left
+    pub(super) fn take(&mut self, weapon: Weapon) -> bool {
+        let ammo = self.get_mut(weapon);
+
+        match ammo.checked_sub(1) {
+            Some(left) => {
+                *ammo = left;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Result of a sonar ping, kept so it stays on screen
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Ping {
+    pub(super) center: Point,
+    /// Some hidden ship cell was in the area at the time
+    pub(super) found: bool,
+}
+
+impl Ping {
+    pub(super) fn covers(&self, point: Point) -> bool {
+        self.center.x.abs_diff(point.x) <= 1 && self.center.y.abs_diff(point.y) <= 1
+    }
+}
+
+impl Board {
+    /// Fires `weapon` at the cell, the same way a shot does with [`Board::hit`]
+    pub fn strike(&mut self, weapon: Weapon, point: Point) -> WebResult<HitDisplayDiff> {
+        if self.get_cell(point).is_none() {
+            return Err(anyhow!("Invalid cell coordinates")
+                .client_error()
+                .code(StatusCode::NOT_FOUND));
+        }
+
+        match weapon {
+            Weapon::Sonar => Ok(self.sonar(point)),
+            Weapon::Airstrike => self.airstrike(point),
+            Weapon::Torpedo => self.torpedo(point),
+        }
+    }
+
+    fn sonar(&mut self, center: Point) -> HitDisplayDiff {
+        let mut ping = Ping {
+            center,
+            found: false,
+        };
+        ping.found = self
+            .cells()
+            .any(|(point, cell)| ping.covers(point) && !cell.exposed && cell.contains_ship());
+        self.pings.push(ping);
+
+        HitDisplayDiff {
+            pinged: self
+                .cells()
+                .filter(|&(point, cell)| ping.covers(point) && !cell.exposed)
+                .filter_map(|(point, _)| Some((point, self.ping_at(point)?)))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn airstrike(&mut self, center: Point) -> WebResult<HitDisplayDiff> {
+        let points: Vec<Point> = (-AIRSTRIKE_REACH..=AIRSTRIKE_REACH)
+            .filter_map(|dy| center.try_add_delta(0, dy))
+            .filter(|&point| self.get_cell(point).is_some_and(|cell| !cell.exposed))
+            .collect();
+
+        if points.is_empty() {
+            return Err(anyhow!("Nothing left to hit around {center}")
+                .client_error()
+                .code(StatusCode::CONFLICT));
+        }
+
+        self.hit_many(&points)
+    }
+
+    // Open water and cells known already don't stop it, islands do
+    fn torpedo(&mut self, start: Point) -> WebResult<HitDisplayDiff> {
+        let mut point = start;
+
+        loop {
+            let Some(cell) = self.get_cell(point) else {
+                // Went off the board without hitting anything
+                return Ok(HitDisplayDiff::default());
+            };
+
+            match (cell.exposed, cell.content) {
+                (_, CellContent::Island) => return Ok(HitDisplayDiff::default()),
+                (false, CellContent::Ship(_) | CellContent::Mine) => return self.hit(point),
+                _ => {}
+            }
+
+            let Some(next) = point.try_add_delta(0, 1) else {
+                return Ok(HitDisplayDiff::default());
+            };
+            point = next;
+        }
+    }
+
+    /// What the sonar told about a cell, if it was pinged.
+    /// A ping that found nothing settles it even if another one found a ship nearby.
+    pub(super) fn ping_at(&self, point: Point) -> Option<bool> {
+        let mut pings = self.pings.iter().filter(|ping| ping.covers(point));
+        let first = pings.next()?;
+
+        Some(first.found && pings.all(|ping| ping.found))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{BoardBuilder, ShipDefinition};
+
+    // A 5x5 board with one torpedo boat in the middle
+    fn board() -> Board {
+        let mut builder = BoardBuilder::square(5).fleet(&[ShipDefinition::new("Торпеда", 1, 1)]);
+        assert!(builder.add_ship(0, vec![Point::new(2, 2)]).is_ok());
+        builder.finish().unwrap()
+    }
+
+    fn fired(diff: &HitDisplayDiff) -> Vec<Point> {
+        diff.fired.iter().map(|shot| shot.point).collect()
+    }
+
+    #[test]
+    fn sonar_pings_the_area_around() {
+        let mut board = board();
+
+        let diff = board.strike(Weapon::Sonar, Point::new(2, 3)).unwrap();
+        assert_eq!(diff.pinged.len(), 9);
+        assert!(diff.pinged.iter().all(|&(_, found)| found));
+        assert!(diff.result().is_none());
+
+        // The corner ping only goes over the four cells on the board
+        let diff = board.strike(Weapon::Sonar, Point::new(4, 0)).unwrap();
+        assert_eq!(diff.pinged.len(), 4);
+        assert!(diff.pinged.iter().all(|&(_, found)| !found));
+        assert!(fired(&diff).is_empty());
+    }
+
+    #[test]
+    fn airstrike_hits_along_the_row() {
+        let mut board = board();
+
+        let diff = board.strike(Weapon::Airstrike, Point::new(0, 2)).unwrap();
+        assert_eq!(
+            fired(&diff),
+            [Point::new(0, 1), Point::new(0, 2), Point::new(0, 3)]
+        );
+
+        // Off the edge of the board nothing is hit
+        let diff = board.strike(Weapon::Airstrike, Point::new(4, 4)).unwrap();
+        assert_eq!(fired(&diff), [Point::new(4, 3), Point::new(4, 4)]);
+
+        let diff = board.strike(Weapon::Airstrike, Point::new(2, 1)).unwrap();
+        assert_eq!(
+            fired(&diff),
+            [Point::new(2, 0), Point::new(2, 1), Point::new(2, 2)]
+        );
+        assert!(diff.is_hit());
+    }
+
+    #[test]
+    fn torpedo_stops_at_the_first_ship() {
+        let mut board = board();
+
+        let diff = board.strike(Weapon::Torpedo, Point::new(0, 0)).unwrap();
+        assert!(fired(&diff).is_empty());
+
+        let diff = board.strike(Weapon::Torpedo, Point::new(2, 0)).unwrap();
+        assert_eq!(fired(&diff), [Point::new(2, 2)]);
+        assert!(diff.is_hit());
+
+        // The sunk ship is open now, so the torpedo runs on and off the board
+        let diff = board.strike(Weapon::Torpedo, Point::new(2, 0)).unwrap();
+        assert!(fired(&diff).is_empty());
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use dashmap::DashMap;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use time::{Date, Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::game::{Seed, seeded_rng};
+
+/// Anonymous, long-lived identity of a browser
+pub type PlayerID = Uuid;
+
+// How long old leaderboards stay around
+const HISTORY_DAYS: i64 = 7;
+
+pub fn today() -> Date {
+    OffsetDateTime::now_utc().date()
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DailyResult {
+    pub player: PlayerID,
+    pub shots: usize,
+    pub finished: OffsetDateTime,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Day {
+    // Only the first attempt counts, the board is the same on the next one
+    attempts: HashSet<PlayerID>,
+    results: Vec<DailyResult>,
+}
+
+/// Shots-to-win for each daily challenge
+pub struct Leaderboard {
+    days: DashMap<Date, Day>,
+    /// Known only to the server, so nobody can work out a daily seed and play it with `?seed=`
+    secret: AtomicU64,
+}
+
+impl Default for Leaderboard {
+    fn default() -> Self {
+        Self {
+            days: DashMap::new(),
+            secret: AtomicU64::new(rand::random()),
+        }
+    }
+}
+
+impl Leaderboard {
+    /// Seed of the daily board, the same for everyone all day
+    pub fn seed(&self, date: Date) -> Seed {
+        let day = Seed::from(date.to_julian_day().unsigned_abs());
+        // Seeds of other days tell nothing about the secret
+        seeded_rng(self.secret() ^ day).random()
+    }
+
+    pub fn secret(&self) -> Seed {
+        self.secret.load(Ordering::Relaxed)
+    }
+
+    /// Keeps the daily boards of a previous run
+    pub fn set_secret(&self, secret: Seed) {
+        self.secret.store(secret, Ordering::Relaxed);
+    }
+
+    /// Registers an attempt, returns false if the player has already had one that day
+    pub fn start(&self, date: Date, player: PlayerID) -> bool {
+        self.days.entry(date).or_default().attempts.insert(player)
+    }
+
+    pub fn has_started(&self, date: Date, player: PlayerID) -> bool {
+        self.days
+            .get(&date)
+            .is_some_and(|day| day.attempts.contains(&player))
+    }
+
+    pub fn record(&self, date: Date, player: PlayerID, shots: usize) {
+        let mut day = self.days.entry(date).or_default();
+
+        if day.results.iter().any(|result| result.player == player) {
+            return;
+        }
+
+        day.results.push(DailyResult {
+            player,
+            shots,
+            finished: OffsetDateTime::now_utc(),
+        });
+    }
+
+    /// Results of the day, best first
+    pub fn results(&self, date: Date) -> Vec<DailyResult> {
+        let mut results = self
+            .days
+            .get(&date)
+            .map(|day| day.results.clone())
+            .unwrap_or_default();
+
+        results.sort_by_key(|result| (result.shots, result.finished));
+        results
+    }
+
+    pub fn cleanup(&self) {
+        let oldest = today() - Duration::days(HISTORY_DAYS);
+        self.days.retain(|&date, _| date >= oldest);
+    }
+
+    pub fn snapshot(&self) -> Vec<(Date, Day)> {
+        self.days
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect()
+    }
+
+    pub fn restore(&self, days: Vec<(Date, Day)>) {
+        for (date, day) in days {
+            self.days.insert(date, day);
+        }
+    }
+}
@@ -1,79 +1,1002 @@
 mod game;
+mod leaderboard;
+mod presets;
+mod replays;
 mod session;
+mod storage;
 mod utils;
 
-use std::sync::Arc;
+use std::{convert::Infallible, path::PathBuf, sync::Arc};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
 use axum::{
-    Router,
-    response::{IntoResponse, Response},
-    routing::{get, patch, put},
+    Json, Router,
+    extract::{Path, Query},
+    http::{StatusCode, header},
+    response::{
+        IntoResponse, Redirect, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::{get, patch, post, put},
 };
+use axum_extra::extract::Form;
 use maud::{Markup, html};
 use pico_args::Arguments;
-use time::Duration;
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
 use tokio::net::TcpListener;
+use tokio_stream::{
+    Stream, StreamExt,
+    wrappers::{BroadcastStream, WatchStream},
+};
 use tower::ServiceBuilder;
 use tower_cookies::CookieManagerLayer;
 use tower_http::compression::CompressionLayer;
 
 use crate::{
-    game::{BoardBuilder, Point, ShipDefinition},
-    session::{SessionManager, SessionOptionExt, Store},
+    game::{
+        Alphabet, Arsenal, Boards, Difficulty, Fleet, FleetError, Game, GameSnapshot, MAX_SIDE,
+        MineEffect, Opponent, Outcome, Placement, Point, Replay, Rules, Salvo, Seat, Seed, Setup,
+        Shape, ShipAddError, ShipDefinition, Weapon, seeded_rng,
+        ui::{RenderMode, render_fleet_check},
+    },
+    leaderboard::today,
+    presets::Preset,
+    session::{MatchID, Notice, Player, SessionManager, SessionOptionExt, Store, match_created},
+    storage::FileBackend,
     utils::{
         assets::asset_handler,
         errors::{AnyhowWebExt, WebResult},
-        htmx::{HtmxRedirect, HtmxTarget},
+        htmx::{HtmxNoSwap, HtmxRedirect, HtmxTarget, HtmxTrigger},
         shutdown,
     },
 };
 
-async fn game_handler(
-    sessions: session::SessionManager,
-    target: HtmxTarget,
-) -> WebResult<Response> {
+// Ends the game for this player, sending them to the final screen
+async fn finish_game(sessions: &SessionManager, player: Player, outcome: &Outcome) -> Response {
+    if let (Outcome::Win, Some(date)) = (outcome, player.game.daily()) {
+        let shots = player.game.shots(player.seat);
+        sessions
+            .store()
+            .leaderboard
+            .record(date, sessions.player_id(), shots);
+    }
+
+    let created = match_created(player.match_id).unwrap_or_else(OffsetDateTime::now_utc);
+    let summary = player.game.summary(created);
+
+    let replays = &sessions.store().replays;
+    replays.save(player.match_id, player.game.snapshot(), summary);
+
+    let url = match player.seat {
+        Seat::Host => format!("/game/over/{}", player.match_id),
+        Seat::Guest => format!("/game/over/{}?guest=true", player.match_id),
+    };
+
+    sessions.delete(player).await;
+    HtmxRedirect::to(url).into_response()
+}
+
+// Shows the move to both players, or ends the game if it's over
+async fn play_turn(sessions: &SessionManager, player: Player, outcome: Outcome) -> Response {
+    let opponent = player.seat.other();
+
+    match outcome {
+        Outcome::Ongoing(turn) => {
+            if player.game.is_online() {
+                let update = html!({
+                    (turn.render_opponent())
+                    (player.game.render_salvo(opponent, RenderMode::Update))
+                    (player.game.render_turn_moves(opponent, &turn))
+                    (player.game.render_limits(opponent, RenderMode::Update))
+                });
+                sessions.notify(
+                    player.match_id,
+                    opponent,
+                    Notice::Update(update.into_string()),
+                );
+            }
+
+            html!({
+                (turn.render())
+                (player.game.render_salvo(player.seat, RenderMode::Update))
+                (player.game.render_arms(player.seat, RenderMode::Update))
+                (player.game.render_turn_moves(player.seat, &turn))
+                (player.game.render_limits(player.seat, RenderMode::Update))
+            })
+            .into_response()
+        }
+        outcome => {
+            sessions.notify(player.match_id, opponent, Notice::Refresh);
+            finish_game(sessions, player, &outcome).await
+        }
+    }
+}
+
+async fn game_handler(sessions: SessionManager, target: HtmxTarget) -> WebResult<Response> {
     // TODO: redirect to new game page instead of error
-    let session = sessions.current().require()?;
-    let board = &session.board;
+    let mut player = sessions.current().await.require()?;
+
+    let opponent = player.seat.other();
 
-    let cell: Point = target
-        .parse()
+    let cell = Point::parse_within(&target, player.game.fleet(opponent).bounds())
         .context("Invalid cell definition")
         .map_err(|e| e.client_error())?;
 
-    let display_diff = board.hit(cell).await?;
+    // A readied weapon takes the place of the shot, it may not even hit the clicked cell
+    if player.game.armed(player.seat).is_some() {
+        let outcome = player.game.fire_weapon(player.seat, cell)?;
+        return Ok((HtmxNoSwap, play_turn(&sessions, player, outcome).await).into_response());
+    }
+
+    // Salvos are aimed cell by cell and fired all at once
+    if player.game.rules().salvo != Salvo::Off {
+        player.game.toggle_aim(player.seat, cell)?;
+        return Ok(player.game.render_aim(player.seat, cell).into_response());
+    }
+
+    let outcome = player.game.fire(player.seat, cell)?;
+    Ok(play_turn(&sessions, player, outcome).await)
+}
+
+async fn salvo_handler(sessions: SessionManager) -> WebResult<Response> {
+    let mut player = sessions.current().await.require()?;
+
+    let outcome = player.game.fire_salvo(player.seat)?;
+    Ok(play_turn(&sessions, player, outcome).await)
+}
+
+async fn weapon_handler(sessions: SessionManager, Path(weapon): Path<Weapon>) -> WebResult<Markup> {
+    let mut player = sessions.current().await.require()?;
+
+    player.game.arm(player.seat, weapon)?;
+    Ok(player.game.render_arms(player.seat, RenderMode::Update))
+}
+
+async fn undo_handler(sessions: SessionManager) -> WebResult<Markup> {
+    let mut player = sessions.current().await.require()?;
+
+    let undo = player.game.undo(player.seat)?;
+    Ok(player.game.render_undo(&undo))
+}
+
+async fn surrender_handler(sessions: SessionManager) -> WebResult<Response> {
+    let mut player = sessions.current().await.require()?;
+
+    let outcome = player.game.surrender(player.seat);
+    Ok(play_turn(&sessions, player, outcome).await)
+}
+
+async fn events_handler(
+    sessions: SessionManager,
+) -> WebResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    let notices = sessions.subscribe().require()?;
+
+    // The stream ends when the server shuts down, it would keep it waiting otherwise
+    let closing = WatchStream::new(sessions.store().closing())
+        .filter(|&closing| closing)
+        .map(|_| None);
+
+    let events = BroadcastStream::new(notices)
+        .map(Some)
+        .merge(closing)
+        .take_while(Option::is_some)
+        .filter_map(|notice| {
+            // Lagged receivers just skip what they missed
+            let event = match notice?.ok()? {
+                Notice::Update(html) => Event::default().event("update").data(html),
+                Notice::Refresh => Event::default().event("refresh").data(""),
+            };
+            Some(Ok(event))
+        });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[derive(Deserialize)]
+struct NewGameQuery {
+    #[serde(default)]
+    difficulty: Difficulty,
+    /// Replays a known computer board instead of a fresh one
+    seed: Option<Seed>,
+    /// Lets the player undo moves
+    #[serde(default)]
+    practice: bool,
+}
+
+fn new_fleet(setup: &Setup) -> Fleet {
+    Fleet::Placing(Placement::new(
+        setup.builder(&mut rand::rng()).fleet(&setup.ships),
+    ))
+}
+
+async fn new_game_handler(
+    sessions: SessionManager,
+    Query(query): Query<NewGameQuery>,
+) -> WebResult<impl IntoResponse> {
+    let setup = Setup::default();
+
+    let seed = query.seed.unwrap_or_else(rand::random);
+    let mut rng = seeded_rng(seed);
+
+    let mut game = Game::solo(
+        new_fleet(&setup),
+        setup
+            .builder(&mut rng)
+            .random(&setup.ships, &mut rng)
+            .context("Failed to place the computer's fleet")?,
+        Opponent::new(query.difficulty),
+    )
+    .with_seed(seed);
+
+    if query.practice {
+        game = game.with_practice();
+    }
+
+    let player = sessions.create(game, false).await?;
+    Ok(player.game.render(player.seat))
+}
+
+/// Everything sent by the custom game form, one list entry per ship row.
+/// Numbers come in as text, so a half-filled form still gets a useful answer.
+#[derive(Deserialize)]
+struct SetupForm {
+    #[serde(default)]
+    width: String,
+    #[serde(default)]
+    height: String,
+    #[serde(default)]
+    name: Vec<String>,
+    #[serde(default)]
+    length: Vec<String>,
+    #[serde(default)]
+    count: Vec<String>,
+    /// Rows of `#` and `.` separated by `/`, empty for a straight ship
+    #[serde(default)]
+    shape: Vec<String>,
+    #[serde(default)]
+    repeat_on_hit: bool,
+    #[serde(default)]
+    ships_touch: bool,
+    /// Either `off`, `fixed` or `per_ship`
+    #[serde(default)]
+    salvo: String,
+    #[serde(default)]
+    salvo_shots: String,
+    #[serde(default)]
+    islands: String,
+    #[serde(default)]
+    mines: String,
+    #[serde(default)]
+    mine_effect: MineEffect,
+    #[serde(default)]
+    sonar: String,
+    #[serde(default)]
+    airstrike: String,
+    #[serde(default)]
+    torpedo: String,
+    /// Empty means no limit
+    #[serde(default)]
+    max_shots: String,
+    /// Minutes, empty means no limit
+    #[serde(default)]
+    time_limit: String,
+    #[serde(default)]
+    difficulty: Difficulty,
+    #[serde(default)]
+    alphabet: Alphabet,
+    /// Name to save the setup under
+    #[serde(default)]
+    preset: String,
+    /// Draws the terrain the fleet is checked on, kept while the form is edited
+    #[serde(default = "rand::random")]
+    seed: Seed,
+}
+
+// Empty or garbled fields count as zero, huge ones are capped
+fn form_number(value: &str) -> u8 {
+    value
+        .trim()
+        .parse::<u64>()
+        .map_or(0, |number| number.min(u8::MAX.into()) as u8)
+}
+
+// Same as `form_number`, but zero turns the limit off
+fn form_limit(value: &str) -> Option<u16> {
+    value
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|&number| number > 0)
+        .map(|number| number.min(u16::MAX.into()) as u16)
+}
+
+impl SetupForm {
+    fn setup(&self) -> WebResult<Setup> {
+        let ships = self
+            .name
+            .iter()
+            .zip(&self.length)
+            .zip(&self.count)
+            .enumerate()
+            .map(|(i, ((name, length), count))| {
+                let ship =
+                    ShipDefinition::new(name.trim(), form_number(length), form_number(count));
+
+                match self.shape.get(i).map(|shape| shape.trim()) {
+                    Some(shape) if !shape.is_empty() => ship
+                        .with_shape(Shape::from(shape.to_string()))
+                        .map_err(|e| anyhow!("{e}").client_error()),
+                    _ => Ok(ship),
+                }
+            })
+            .collect::<WebResult<_>>()?;
+
+        Ok(Setup {
+            width: form_number(&self.width),
+            height: form_number(&self.height),
+            ships,
+            rules: Rules {
+                repeat_on_hit: self.repeat_on_hit,
+                ships_touch: self.ships_touch,
+                salvo: match self.salvo.as_str() {
+                    "fixed" => Salvo::Fixed(form_number(&self.salvo_shots).max(1)),
+                    "per_ship" => Salvo::PerShip,
+                    _ => Salvo::Off,
+                },
+                islands: form_number(&self.islands),
+                mines: form_number(&self.mines),
+                mine_effect: self.mine_effect,
+                arsenal: Arsenal {
+                    sonar: form_number(&self.sonar),
+                    airstrike: form_number(&self.airstrike),
+                    torpedo: form_number(&self.torpedo),
+                },
+                max_shots: form_limit(&self.max_shots),
+                time_limit: form_limit(&self.time_limit),
+            },
+            alphabet: self.alphabet,
+        })
+    }
+}
+
+// The search for room can take a while, so it runs off the async runtime
+async fn draw_boards(setup: Setup, seed: Seed) -> Result<Result<Boards, FleetError>> {
+    Ok(tokio::task::spawn_blocking(move || setup.boards(seed)).await?)
+}
+
+async fn fleet_check_handler(Form(form): Form<SetupForm>) -> WebResult<Markup> {
+    let check = draw_boards(form.setup()?, form.seed).await?;
+    Ok(render_fleet_check(&check.map(|boards| boards.report)))
+}
+
+async fn new_custom_game_handler(
+    sessions: SessionManager,
+    Form(form): Form<SetupForm>,
+) -> WebResult<Response> {
+    let setup = form.setup()?;
+
+    // The game starts on exactly the boards the fleet was checked on
+    let boards = match draw_boards(setup.clone(), form.seed).await? {
+        Ok(boards) => boards,
+        Err(e) => {
+            let presets = sessions.store().presets.names(sessions.player_id());
+            let status = e.to_string();
+
+            return Ok((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                screen(
+                    "waves",
+                    custom_setup(&presets, &setup, form.seed, form.difficulty, Some(&status)),
+                ),
+            )
+                .into_response());
+        }
+    };
+
+    let game = Game::solo(
+        Fleet::Placing(Placement::new(boards.player)),
+        boards.computer,
+        Opponent::new(form.difficulty),
+    )
+    .with_rules(setup.rules);
+
+    let player = sessions.create(game, false).await?;
+    Ok(player.game.render(player.seat).into_response())
+}
+
+async fn custom_row_add_handler(
+    sessions: SessionManager,
+    Form(form): Form<SetupForm>,
+) -> WebResult<Markup> {
+    let mut setup = form.setup()?;
+    setup.ships.push(ShipDefinition::new("", 1, 1));
+
+    let presets = sessions.store().presets.names(sessions.player_id());
+    Ok(custom_setup(
+        &presets,
+        &setup,
+        form.seed,
+        form.difficulty,
+        None,
+    ))
+}
+
+async fn custom_row_remove_handler(
+    sessions: SessionManager,
+    Path(row): Path<usize>,
+    Form(form): Form<SetupForm>,
+) -> WebResult<Markup> {
+    let mut setup = form.setup()?;
+    if row < setup.ships.len() {
+        setup.ships.remove(row);
+    }
+
+    let presets = sessions.store().presets.names(sessions.player_id());
+    Ok(custom_setup(
+        &presets,
+        &setup,
+        form.seed,
+        form.difficulty,
+        None,
+    ))
+}
+
+#[derive(Deserialize)]
+struct PresetQuery {
+    /// Saved preset to load, the classic game if empty
+    #[serde(default)]
+    load: String,
+}
+
+async fn custom_preset_load_handler(
+    sessions: SessionManager,
+    Query(query): Query<PresetQuery>,
+) -> WebResult<Markup> {
+    let player = sessions.player_id();
+
+    let preset = match query.load.as_str() {
+        "" => Preset {
+            name: String::new(),
+            setup: Setup::default(),
+            difficulty: Difficulty::default(),
+        },
+        name => sessions
+            .store()
+            .presets
+            .get(player, name)
+            .context("No such preset")
+            .map_err(|e| e.client_error().code(StatusCode::NOT_FOUND))?,
+    };
+
+    let presets = sessions.store().presets.names(player);
+    Ok(custom_setup(
+        &presets,
+        &preset.setup,
+        rand::random(),
+        preset.difficulty,
+        None,
+    ))
+}
 
-    if board.is_win().await {
-        sessions.delete(session).await;
-        Ok(HtmxRedirect::to("/game/win").into_response())
+async fn custom_preset_save_handler(
+    sessions: SessionManager,
+    Form(form): Form<SetupForm>,
+) -> WebResult<Markup> {
+    let player = sessions.player_id();
+    let presets = &sessions.store().presets;
+
+    let setup = form.setup()?;
+    let name = form.preset.trim();
+
+    let status = if name.is_empty() {
+        "Введите название набора".to_string()
+    } else if let Err(e) = draw_boards(setup.clone(), form.seed).await? {
+        e.to_string()
+    } else if presets.save(
+        player,
+        Preset {
+            name: name.to_string(),
+            setup: setup.clone(),
+            difficulty: form.difficulty,
+        },
+    ) {
+        format!("Набор «{name}» сохранён")
     } else {
-        Ok(display_diff.render().await.into_response())
+        "Сохранено слишком много наборов".to_string()
+    };
+
+    Ok(custom_setup(
+        &presets.names(player),
+        &setup,
+        form.seed,
+        form.difficulty,
+        Some(&status),
+    ))
+}
+
+async fn new_daily_game_handler(sessions: SessionManager) -> WebResult<impl IntoResponse> {
+    let date = today();
+
+    if !sessions
+        .store()
+        .leaderboard
+        .start(date, sessions.player_id())
+    {
+        return Err(anyhow!("Today's challenge has already been played")
+            .client_error()
+            .code(StatusCode::CONFLICT));
     }
+
+    let setup = Setup::default();
+    let seed = sessions.store().leaderboard.seed(date);
+    let mut rng = seeded_rng(seed);
+
+    let game = Game::solo(
+        new_fleet(&setup),
+        setup
+            .builder(&mut rng)
+            .random(&setup.ships, &mut rng)
+            .context("Failed to place the computer's fleet")?,
+        Opponent::new(Difficulty::default()),
+    )
+    .with_seed(seed)
+    .with_daily(date);
+
+    let player = sessions.create(game, false).await?;
+    Ok(player.game.render(player.seat))
 }
 
-async fn new_game_handler(sessions: SessionManager) -> WebResult<impl IntoResponse> {
-    let session = sessions.create(
-        BoardBuilder::square(10)
-            .random(&[
-                ShipDefinition::new("Линкор", 4, 1),
-                ShipDefinition::new("Крейсер", 3, 2),
-                ShipDefinition::new("Эсминец", 2, 3),
-                ShipDefinition::new("Торпеда", 1, 4),
-            ])
-            .await?,
+async fn new_online_game_handler(sessions: SessionManager) -> WebResult<impl IntoResponse> {
+    let setup = Setup::default();
+    let game = Game::online(new_fleet(&setup), new_fleet(&setup));
+
+    let player = sessions.create(game, true).await?;
+
+    Ok(html!({
+        (player.game.render(player.seat))
+        (invite(player.match_id))
+    }))
+}
+
+async fn continue_game_handler(sessions: SessionManager) -> WebResult<Response> {
+    let mut player = sessions.current().await.require()?;
+
+    // Nobody may be around to move when the time runs out
+    player.game.check_time();
+
+    match player.game.result(player.seat) {
+        Some(outcome) => Ok(finish_game(&sessions, player, &outcome).await),
+        None => Ok(player.game.render(player.seat).into_response()),
+    }
+}
+
+// Only finished games, one in progress would show the opponent's fleet
+async fn export_handler(
+    sessions: SessionManager,
+    Path(id): Path<MatchID>,
+) -> WebResult<impl IntoResponse> {
+    let recording = sessions.store().replays.get(id).ok_or(
+        anyhow!("Game not found")
+            .client_error()
+            .code(StatusCode::NOT_FOUND),
     )?;
 
-    let board = &session.board;
-    Ok(board.render().await)
+    // Would show everyone's daily board
+    if recording.game.daily().is_some() {
+        return Err(anyhow!("Daily challenges can't be exported")
+            .client_error()
+            .code(StatusCode::FORBIDDEN));
+    }
+
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"battleships.json\"",
+        )],
+        Json(recording.game),
+    ))
+}
+
+async fn import_handler(
+    sessions: SessionManager,
+    Json(snapshot): Json<GameSnapshot>,
+) -> WebResult<impl IntoResponse> {
+    let game =
+        Game::restore(&snapshot).map_err(|e| anyhow!("Invalid snapshot: {e:#}").client_error())?;
+
+    if game.daily().is_some() {
+        return Err(anyhow!("Daily challenges can't be imported").client_error());
+    }
+
+    let open = game.is_online();
+    sessions.create(game, open).await?;
+
+    Ok(Redirect::to("/play"))
 }
 
-async fn continue_game_handler(sessions: SessionManager) -> WebResult<impl IntoResponse> {
-    let session = sessions.current().require()?;
-    Ok(session.board.render().await)
+async fn placement_handler(
+    sessions: SessionManager,
+    trigger: HtmxTrigger,
+) -> WebResult<impl IntoResponse> {
+    let mut player = sessions.current().await.require()?;
+    let bounds = player.game.fleet(player.seat).bounds();
+    let placement = player.game.fleet_mut(player.seat).placing()?;
+
+    let cell = Point::parse_within(&trigger, bounds)
+        .context("Invalid cell definition")
+        .map_err(|e| e.client_error())?;
+
+    let status = match placement.place(cell) {
+        Ok(()) => None,
+        Err(ShipAddError::InternalError(e)) => return Err(e.into()),
+        Err(e) => Some(e.to_string()),
+    };
+
+    Ok(placement.render(status))
 }
 
-fn page(modifier: &'static str, html: Markup) -> Markup {
+async fn placement_select_handler(
+    sessions: SessionManager,
+    Path(kind): Path<usize>,
+) -> WebResult<impl IntoResponse> {
+    let mut player = sessions.current().await.require()?;
+    let placement = player.game.fleet_mut(player.seat).placing()?;
+
+    placement.select(kind).map_err(|e| e.client_error())?;
+    Ok(placement.render(None))
+}
+
+async fn placement_rotate_handler(sessions: SessionManager) -> WebResult<impl IntoResponse> {
+    let mut player = sessions.current().await.require()?;
+    let placement = player.game.fleet_mut(player.seat).placing()?;
+
+    placement.rotate();
+    Ok(placement.render(None))
+}
+
+async fn placement_flip_handler(sessions: SessionManager) -> WebResult<impl IntoResponse> {
+    let mut player = sessions.current().await.require()?;
+    let placement = player.game.fleet_mut(player.seat).placing()?;
+
+    placement.flip();
+    Ok(placement.render(None))
+}
+
+async fn placement_random_handler(sessions: SessionManager) -> WebResult<impl IntoResponse> {
+    let mut player = sessions.current().await.require()?;
+    let placement = player.game.fleet_mut(player.seat).placing()?;
+
+    let status = match placement.randomize() {
+        Ok(()) => None,
+        Err(e) => Some(e.to_string()),
+    };
+
+    Ok(placement.render(status))
+}
+
+async fn placement_clear_handler(sessions: SessionManager) -> WebResult<impl IntoResponse> {
+    let mut player = sessions.current().await.require()?;
+    let placement = player.game.fleet_mut(player.seat).placing()?;
+
+    placement.clear();
+    Ok(placement.render(None))
+}
+
+async fn placement_done_handler(sessions: SessionManager) -> WebResult<impl IntoResponse> {
+    let mut player = sessions.current().await.require()?;
+    let fleet = player.game.fleet_mut(player.seat);
+    let placement = fleet.placing()?;
+
+    let Some(board) = placement.finish() else {
+        let status = "Сначала расставьте все корабли".to_string();
+        return Ok(placement.render(Some(status)));
+    };
+
+    *fleet = Fleet::Ready(board);
+
+    // Let an opponent waiting for us see the battle start
+    let opponent = player.seat.other();
+    if player.game.is_ready(opponent) {
+        sessions.notify(player.match_id, opponent, Notice::Refresh);
+    }
+
+    Ok(player.game.render(player.seat))
+}
+
+async fn join_handler(
+    sessions: SessionManager,
+    Path(match_id): Path<MatchID>,
+) -> WebResult<impl IntoResponse> {
+    let player = sessions.join(match_id).await?;
+
+    let dismiss_invite = html!({ div #invite hx-swap-oob="delete" {} });
+    sessions.notify(
+        match_id,
+        player.seat.other(),
+        Notice::Update(dismiss_invite.into_string()),
+    );
+
+    Ok(Redirect::to("/play"))
+}
+
+async fn page_play(sessions: SessionManager) -> Response {
+    match sessions.current().await {
+        Some(player) => document(player.game.render(player.seat)).into_response(),
+        None => Redirect::to("/").into_response(),
+    }
+}
+
+fn invite(match_id: MatchID) -> Markup {
+    html!({
+        #invite {
+            "Ссылка для друга: "
+            a href=(format!("/join/{match_id}")) target="_blank" {"присоединиться"}
+        }
+    })
+}
+
+async fn page_lobby(sessions: SessionManager) -> Markup {
+    let matches = sessions.store().open_matches();
+
+    page(
+        "waves",
+        html!({
+            #lobby {
+                #lobby-title {"Открытые игры"}
+
+                @for match_id in &matches {
+                    a .lobby-entry href=(format!("/join/{match_id}")) {
+                        .btn.control {"Игра " (match_id.simple().to_string()[24..])}
+                    }
+                }
+
+                @if matches.is_empty() {
+                    .lobby-empty {"Пока никого нет"}
+                }
+
+                .btn.control
+                    hx-put="/game/online"
+                    hx-target="body"
+                    hx-swap="innerHTML"
+                    {"Создать игру"};
+            }
+            a #win-exit href="/" {
+                .btn.exit  { "Выход" }
+            }
+        }),
+    )
+}
+
+fn custom_setup(
+    presets: &[String],
+    setup: &Setup,
+    seed: Seed,
+    difficulty: Difficulty,
+    status: Option<&str>,
+) -> Markup {
+    let salvo_shots = match setup.rules.salvo {
+        Salvo::Fixed(shots) => shots,
+        _ => 3,
+    };
+
+    html!({
+        form #custom-setup hx-put="/game/custom" hx-target="body" hx-swap="innerHTML" {
+            #lobby-title {"Своя игра"}
+            input type="hidden" name="seed" value=(seed);
+
+            .setup-row {
+                label {
+                    "Ширина "
+                    input type="number" name="width" min="1" max=(MAX_SIDE) value=(setup.width);
+                }
+                label {
+                    "Высота "
+                    input type="number" name="height" min="1" max=(MAX_SIDE) value=(setup.height);
+                }
+                label {
+                    "Столбцы "
+                    select name="alphabet" {
+                        @for option in Alphabet::ALL {
+                            option value=(option.id()) selected[option == setup.alphabet] {(option.label())}
+                        }
+                    }
+                }
+            }
+
+            .ship-row .ship-header {
+                span {"Корабль"}
+                span {"Длина"}
+                span {"Форма"}
+                span {"Сколько"}
+                span {}
+            }
+            @for (i, ship) in setup.ships.iter().enumerate() {
+                .ship-row {
+                    input name="name" placeholder="Название" value=(ship.name());
+                    input type="number" name="length" min="1" max=(MAX_SIDE) value=(ship.length());
+                    input name="shape" placeholder="###/.#."
+                        title="Клетки корабля по рядам: # — палуба, . — пусто, ряды через /"
+                        value=(ship.custom_shape().map(ToString::to_string).unwrap_or_default());
+                    input type="number" name="count" min="0" value=(ship.count());
+                    .btn.control
+                        title="Убрать"
+                        hx-post=(format!("/custom/rows/remove/{i}"))
+                        hx-target="#custom-setup"
+                        {"✕"}
+                }
+            }
+            .btn.control hx-post="/custom/rows/add" hx-target="#custom-setup" {"Добавить корабль"}
+
+            .setup-row {
+                label {
+                    input type="checkbox" name="repeat_on_hit" value="true"
+                        checked[setup.rules.repeat_on_hit];
+                    " Попал — стреляй ещё"
+                }
+                label {
+                    input type="checkbox" name="ships_touch" value="true"
+                        checked[setup.rules.ships_touch];
+                    " Корабли могут касаться"
+                }
+                label {
+                    "Залп "
+                    select name="salvo" {
+                        option value="off" selected[setup.rules.salvo == Salvo::Off] {"нет"}
+                        option value="fixed" selected[matches!(setup.rules.salvo, Salvo::Fixed(_))] {"выстрелов:"}
+                        option value="per_ship" selected[setup.rules.salvo == Salvo::PerShip] {"по числу кораблей"}
+                    }
+                    input type="number" name="salvo_shots" min="1" value=(salvo_shots);
+                }
+            }
+
+            .setup-row {
+                label {
+                    "Острова "
+                    input type="number" name="islands" min="0" value=(setup.rules.islands);
+                }
+                label {
+                    "Мины "
+                    input type="number" name="mines" min="0" value=(setup.rules.mines);
+                }
+                label {
+                    "Мина "
+                    select name="mine_effect" {
+                        option value="skip_turn" selected[setup.rules.mine_effect == MineEffect::SkipTurn] {"пропуск хода"}
+                        option value="reveal" selected[setup.rules.mine_effect == MineEffect::Reveal] {"выдаёт палубу"}
+                    }
+                }
+            }
+
+            .setup-row {
+                label title="Показывает, есть ли корабль в квадрате 3×3" {
+                    "Сонар "
+                    input type="number" name="sonar" min="0" value=(setup.rules.arsenal.sonar);
+                }
+                label title="Бьёт по трём клеткам в ряд" {
+                    "Авиаудар "
+                    input type="number" name="airstrike" min="0" value=(setup.rules.arsenal.airstrike);
+                }
+                label title="Идёт по ряду вправо до первого корабля" {
+                    "Торпеда "
+                    input type="number" name="torpedo" min="0" value=(setup.rules.arsenal.torpedo);
+                }
+            }
+
+            .setup-row {
+                label title="Кто не успел потопить флот за это число выстрелов, проигрывает" {
+                    "Выстрелов не больше "
+                    input type="number" name="max_shots" min="0" value=[setup.rules.max_shots];
+                }
+                label title="Когда время выходит, проигрывает тот, чей ход" {
+                    "Минут на игру "
+                    input type="number" name="time_limit" min="0" value=[setup.rules.time_limit];
+                }
+                label {
+                    "Соперник "
+                    select name="difficulty" {
+                        @for option in Difficulty::ALL {
+                            option value=(option.id()) selected[option == difficulty] {(option.label())}
+                        }
+                    }
+                }
+            }
+
+            div hx-post="/game/fleet/check"
+                hx-trigger="load, input from:closest form delay:300ms"
+                hx-target="#fleet-check"
+                { #fleet-check {} }
+
+            @if let Some(status) = status {
+                #setup-status {(status)}
+            }
+
+            .setup-row {
+                select name="load" hx-get="/custom/preset" hx-target="#custom-setup" {
+                    option value="" {"Классика"}
+                    @for name in presets {
+                        option value=(name) {(name)}
+                    }
+                }
+                input name="preset" placeholder="Название набора";
+                .btn.control hx-post="/custom/preset" hx-target="#custom-setup" {"Сохранить"}
+            }
+
+            button .btn.control type="submit" {"Начать игру"}
+        }
+        a #win-exit href="/" {
+            .btn.exit  { "Выход" }
+        }
+    })
+}
+
+async fn page_custom(sessions: SessionManager) -> Markup {
+    let presets = sessions.store().presets.names(sessions.player_id());
+
+    page(
+        "waves",
+        custom_setup(
+            &presets,
+            &Setup::default(),
+            rand::random(),
+            Difficulty::default(),
+            None,
+        ),
+    )
+}
+
+async fn page_daily(sessions: SessionManager) -> Markup {
+    let date = today();
+    let leaderboard = &sessions.store().leaderboard;
+    let player = sessions.player_id();
+
+    let results = leaderboard.results(date);
+
+    page(
+        "waves",
+        html!({
+            #lobby {
+                #lobby-title {"Поле дня " (date)}
+
+                @if results.is_empty() {
+                    .lobby-empty {"Пока никто не победил"}
+                } @else {
+                    table #leaderboard {
+                        tr { th {"#"} th {"Игрок"} th {"Выстрелов"} }
+                        @for (place, result) in results.iter().enumerate() {
+                            tr .current[result.player == player] {
+                                td {(place + 1)}
+                                td {
+                                    @if result.player == player {
+                                        "Вы"
+                                    } @else {
+                                        "Игрок " (result.player.simple().to_string()[28..])
+                                    }
+                                }
+                                td {(result.shots)}
+                            }
+                        }
+                    }
+                }
+
+                @if leaderboard.has_started(date, player) {
+                    .lobby-empty {"Вы уже играли сегодня"}
+                } @else {
+                    .btn.control
+                        hx-put="/game/daily"
+                        hx-target="body"
+                        hx-swap="innerHTML"
+                        {"Играть"};
+                }
+            }
+            a #win-exit href="/" {
+                .btn.exit  { "Выход" }
+            }
+        }),
+    )
+}
+
+fn document(body: Markup) -> Markup {
     html!(
         (maud::DOCTYPE)
         html lang="ru" {
@@ -87,29 +1010,73 @@ fn page(modifier: &'static str, html: Markup) -> Markup {
                 link rel="icon" type="image/png" sizes="32x32" href="/favicon/32x32.png";
                 link rel="icon" type="image/png" sizes="96x96" href="/favicon/96x96.png";
 
-                meta name="htmx-config" content={r#"{"defaultSwapStyle": "outerHTML"}"#};
+                // Forms sent back with their errors come as 422 and still get swapped in
+                meta name="htmx-config" content={r#"{"defaultSwapStyle": "outerHTML", "responseHandling": [{"code": "204", "swap": false}, {"code": "[23]..", "swap": true}, {"code": "422", "swap": true}, {"code": "[45]..", "swap": false, "error": true}, {"code": "...", "swap": false}]}"#};
                 script src="/vendor/htmx.min.js" {}
+                script src="/events.js" {}
             };
 
-            body {
-                #screen class=(modifier) {
-                    #display class=(modifier) {
-                        (html)
-                    }
-                }
+            body hx-ext="events" {
+                (body)
             }
         }
     )
 }
 
+fn screen(modifier: &'static str, html: Markup) -> Markup {
+    html!({
+        #screen class=(modifier) {
+            #display class=(modifier) {
+                (html)
+            }
+        }
+    })
+}
+
+fn page(modifier: &'static str, html: Markup) -> Markup {
+    document(screen(modifier, html))
+}
+
+async fn new_game_menu() -> Markup {
+    html!({
+        #display .waves {
+            @for difficulty in Difficulty::ALL {
+                .btn.menu
+                    hx-put=(format!("/game?difficulty={}", difficulty.id()))
+                    hx-target="body"
+                    hx-swap="innerHTML"
+                    {(difficulty.label())};
+            }
+
+            .btn.menu
+                title="Ходы можно отменять"
+                hx-put="/game?practice=true"
+                hx-target="body"
+                hx-swap="innerHTML"
+                {"Тренировка"};
+
+            a href="/daily" {
+                .btn.menu {"Поле дня"}
+            }
+
+            a href="/custom" {
+                .btn.menu {"Своя игра"}
+            }
+
+            a href="/lobby" {
+                .btn.menu {"С другом"}
+            }
+        }
+    })
+}
+
 async fn page_app(sessions: SessionManager) -> impl IntoResponse {
     page(
         "waves",
         html!({
             .btn.menu
-                hx-put={"/game"}
-                hx-target="body"
-                hx-swap="innerHTML"
+                hx-get={"/game/new"}
+                hx-target="#display"
                 {"Начать игру"};
 
             @if sessions.current_exists() {
@@ -123,16 +1090,69 @@ async fn page_app(sessions: SessionManager) -> impl IntoResponse {
     )
 }
 
-pub async fn page_win() -> Markup {
-    page(
-        "waves",
-        html!({
-            #win-text {"Победа!"}
-            a #win-exit href="/" {
-                .btn.exit  { "Выход" }
-            }
-        }),
-    )
+#[derive(Deserialize)]
+struct OverQuery {
+    /// Shows the game as the player who joined it
+    #[serde(default)]
+    guest: bool,
+}
+
+async fn page_game_over(
+    sessions: SessionManager,
+    Path(id): Path<MatchID>,
+    Query(query): Query<OverQuery>,
+) -> WebResult<Markup> {
+    let recording = sessions.store().replays.get(id).ok_or(
+        anyhow!("Game not found")
+            .client_error()
+            .code(StatusCode::NOT_FOUND),
+    )?;
+
+    let game = Game::restore(&recording.game).context("Failed to restore the game")?;
+    let seat = if query.guest { Seat::Guest } else { Seat::Host };
+
+    let summary = recording
+        .summary
+        .unwrap_or_else(|| game.summary(match_created(id).unwrap_or(recording.finished)));
+
+    // Everyone plays the same daily board, a replay would give it away
+    let replay = game.daily().is_none().then_some(id);
+
+    let over = game.render_over(seat, &summary, replay);
+    Ok(document(over))
+}
+
+#[derive(Deserialize)]
+struct ReplayQuery {
+    /// Moves made so far, the whole game if missing
+    step: Option<usize>,
+    #[serde(default)]
+    autoplay: bool,
+}
+
+async fn page_replay(
+    sessions: SessionManager,
+    Path(id): Path<MatchID>,
+    Query(query): Query<ReplayQuery>,
+) -> WebResult<Markup> {
+    let recording = sessions.store().replays.get(id).ok_or(
+        anyhow!("Replay not found")
+            .client_error()
+            .code(StatusCode::NOT_FOUND),
+    )?;
+    let snapshot = recording.game;
+
+    if snapshot.daily().is_some() {
+        return Err(anyhow!("Daily challenges can't be replayed")
+            .client_error()
+            .code(StatusCode::FORBIDDEN));
+    }
+
+    let replay = Replay::new(&snapshot, query.step.unwrap_or(usize::MAX))
+        .context("Failed to replay the game")?;
+
+    let url = format!("/replay/{id}");
+    Ok(document(replay.render(&url, query.autoplay)))
 }
 
 async fn listener_from_args(args: &mut Arguments) -> Result<TcpListener> {
@@ -155,16 +1175,56 @@ async fn main() -> Result<()> {
     let mut args = Arguments::from_env();
     let listener = listener_from_args(&mut args).await?;
 
-    let store = Arc::new(Store::new(Duration::days(1)));
-    let store = store.with_cleanup();
+    let mut store = Store::new(Duration::days(1));
+    if let Some(path) = args.opt_value_from_str::<_, PathBuf>("--data")? {
+        tracing::info!("Keeping board data in {}", path.display());
+        store = store.with_backend(FileBackend::new(path));
+    }
+
+    store.load().await?;
+
+    let store = Arc::new(store)
+        .with_cleanup()
+        .with_flush(Duration::minutes(1));
 
     let router = Router::new()
         .route("/", get(page_app))
-        .route("/game/win", get(page_win))
+        .route("/game/over/{id}", get(page_game_over))
+        .route("/game/over/{id}/export", get(export_handler))
+        .route("/replay/{id}", get(page_replay))
         //
+        .route("/game/new", get(new_game_menu))
         .route("/game", get(continue_game_handler))
         .route("/game", put(new_game_handler))
         .route("/game", patch(game_handler))
+        .route("/game/salvo", post(salvo_handler))
+        .route("/game/weapon/{weapon}", post(weapon_handler))
+        .route("/game/undo", post(undo_handler))
+        .route("/game/surrender", post(surrender_handler))
+        .route("/game/online", put(new_online_game_handler))
+        .route("/game/daily", put(new_daily_game_handler))
+        .route("/game/custom", put(new_custom_game_handler))
+        .route("/game/fleet/check", post(fleet_check_handler))
+        .route("/game/events", get(events_handler))
+        .route("/game/import", post(import_handler))
+        //
+        .route("/play", get(page_play))
+        .route("/lobby", get(page_lobby))
+        .route("/daily", get(page_daily))
+        .route("/custom", get(page_custom))
+        .route("/custom/rows/add", post(custom_row_add_handler))
+        .route("/custom/rows/remove/{row}", post(custom_row_remove_handler))
+        .route("/custom/preset", get(custom_preset_load_handler))
+        .route("/custom/preset", post(custom_preset_save_handler))
+        .route("/join/{id}", get(join_handler))
+        //
+        .route("/game/placement", patch(placement_handler))
+        .route("/game/placement/select/{kind}", post(placement_select_handler))
+        .route("/game/placement/rotate", post(placement_rotate_handler))
+        .route("/game/placement/flip", post(placement_flip_handler))
+        .route("/game/placement/random", post(placement_random_handler))
+        .route("/game/placement/clear", post(placement_clear_handler))
+        .route("/game/placement/done", post(placement_done_handler))
         //
         .route("/{*path}", get(asset_handler))
         .layer(
@@ -174,8 +1234,21 @@ async fn main() -> Result<()> {
         )
         .with_state(store.clone());
 
-    Ok(axum::serve(listener, router)
-        .with_graceful_shutdown(shutdown::signal())
+    let closing = store.clone();
+    let signal = async move {
+        shutdown::signal().await;
+
+        if let Err(e) = closing.close().await {
+            tracing::error!("Failed to flush board data: {e:#}");
+        }
+    };
+
+    axum::serve(listener, router)
+        .with_graceful_shutdown(signal)
         .await
-        .unwrap())
+        .context("Server error")?;
+
+    // Requests that were still running may have changed something since
+
+    store.flush().await
 }
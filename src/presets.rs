@@ -0,0 +1,67 @@
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::{Difficulty, Setup},
+    leaderboard::PlayerID,
+};
+
+// Keeps a single player from filling up the store
+const MAX_PRESETS: usize = 20;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    pub setup: Setup,
+    #[serde(default)]
+    pub difficulty: Difficulty,
+}
+
+/// Custom game setups saved by each player under a name
+#[derive(Default)]
+pub struct Presets {
+    players: DashMap<PlayerID, Vec<Preset>>,
+}
+
+impl Presets {
+    /// Saves the preset, replacing one with the same name.
+    /// Returns false if the player has no room for another one.
+    pub fn save(&self, player: PlayerID, preset: Preset) -> bool {
+        let mut presets = self.players.entry(player).or_default();
+
+        match presets.iter().position(|saved| saved.name == preset.name) {
+            Some(i) => presets[i] = preset,
+            None if presets.len() < MAX_PRESETS => presets.push(preset),
+            None => return false,
+        }
+        true
+    }
+
+    pub fn get(&self, player: PlayerID, name: &str) -> Option<Preset> {
+        self.players
+            .get(&player)?
+            .iter()
+            .find(|preset| preset.name == name)
+            .cloned()
+    }
+
+    pub fn names(&self, player: PlayerID) -> Vec<String> {
+        self.players
+            .get(&player)
+            .map(|presets| presets.iter().map(|preset| preset.name.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn snapshot(&self) -> Vec<(PlayerID, Vec<Preset>)> {
+        self.players
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect()
+    }
+
+    pub fn restore(&self, players: Vec<(PlayerID, Vec<Preset>)>) {
+        for (player, presets) in players {
+            self.players.insert(player, presets);
+        }
+    }
+}
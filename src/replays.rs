@@ -0,0 +1,60 @@
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    game::{GameSnapshot, Summary},
+    session::MatchID,
+};
+
+// How long finished games can be looked at again
+const HISTORY_DAYS: i64 = 30;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub finished: OffsetDateTime,
+    pub game: GameSnapshot,
+    /// Missing for games recorded before stats were kept
+    #[serde(default)]
+    pub summary: Option<Summary>,
+}
+
+/// Finished games, kept under the id of their match for the final screen and the replay viewer
+#[derive(Default)]
+pub struct Replays {
+    games: DashMap<MatchID, Recording>,
+}
+
+impl Replays {
+    /// Keeps the game, unless the other player of the match got to it first
+    pub fn save(&self, id: MatchID, game: GameSnapshot, summary: Summary) {
+        self.games.entry(id).or_insert_with(|| Recording {
+            finished: OffsetDateTime::now_utc(),
+            game,
+            summary: Some(summary),
+        });
+    }
+
+    pub fn get(&self, id: MatchID) -> Option<Recording> {
+        self.games.get(&id).map(|recording| recording.clone())
+    }
+
+    pub fn cleanup(&self) {
+        let oldest = OffsetDateTime::now_utc() - Duration::days(HISTORY_DAYS);
+        self.games
+            .retain(|_, recording| recording.finished >= oldest);
+    }
+
+    pub fn snapshot(&self) -> Vec<(MatchID, Recording)> {
+        self.games
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect()
+    }
+
+    pub fn restore(&self, games: Vec<(MatchID, Recording)>) {
+        for (id, recording) in games {
+            self.games.insert(id, recording);
+        }
+    }
+}
@@ -5,16 +5,19 @@ use axum::{
     extract::{FromRef, FromRequestParts},
     http::StatusCode,
 };
-use dashmap::{
-    DashMap, Entry,
-    mapref::one::{Ref, RefMut},
-};
-use time::{Duration, OffsetDateTime, UtcDateTime};
+use dashmap::{DashMap, Entry};
+use serde::{Deserialize, Serialize};
+use time::{Date, Duration, OffsetDateTime, UtcDateTime};
+use tokio::sync::{Mutex, OwnedMutexGuard, broadcast, watch};
 use tower_cookies::{Cookie, Cookies};
 use uuid::Uuid;
 
 use crate::{
-    game::Board,
+    game::{Game, GameSnapshot, Seat, Seed},
+    leaderboard::{Day, Leaderboard, PlayerID},
+    presets::{Preset, Presets},
+    replays::{Recording, Replays},
+    storage::Backend,
     utils::{
         errors::{AnyhowWebExt, WebError, WebResult},
         scheduler,
@@ -22,58 +25,319 @@ use crate::{
 };
 
 type SessionID = Uuid;
+pub type MatchID = Uuid;
+
+/// When the match was set up, UUIDv7 carry their creation time
+pub fn match_created(id: MatchID) -> Option<OffsetDateTime> {
+    let (seconds, _) = id.get_timestamp()?.to_unix();
+    OffsetDateTime::from_unix_timestamp(seconds.try_into().ok()?).ok()
+}
 // TODO: typed cookies
 static SESSION_COOKIE_REF: &str = "board";
+static PLAYER_COOKIE_REF: &str = "player";
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Session {
+    expires: OffsetDateTime,
+    match_id: MatchID,
+    seat: Seat,
+}
+
+/// Something that happened in a game while the player wasn't looking
+#[derive(Clone)]
+pub enum Notice {
+    /// Out-of-band fragments to apply to the current screen
+    Update(String),
+    /// The whole screen is stale and has to be requested again
+    Refresh,
+}
 
-pub struct Session {
+/// A game shared between the sessions sitting at it
+struct Match {
     expires: OffsetDateTime,
-    pub board: Board,
+    players: [Option<SessionID>; 2],
+    // Whether the guest seat is waiting for someone to join
+    open: bool,
+    game: Arc<Mutex<Game>>,
+    notices: [broadcast::Sender<Notice>; 2],
 }
 
-type SessionRef<'a> = Ref<'a, SessionID, Session>;
-type SessionRefMut<'a> = RefMut<'a, SessionID, Session>;
+impl Match {
+    fn channels() -> [broadcast::Sender<Notice>; 2] {
+        [broadcast::channel(16).0, broadcast::channel(16).0]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct MatchSnapshot {
+    expires: OffsetDateTime,
+    players: [Option<SessionID>; 2],
+    open: bool,
+    game: GameSnapshot,
+}
+
+/// Everything in the store, in a form that can be written out
+#[derive(Serialize, Deserialize)]
+pub struct StoreSnapshot {
+    sessions: Vec<(SessionID, Session)>,
+    matches: Vec<(MatchID, MatchSnapshot)>,
+    #[serde(default)]
+    leaderboard: Vec<(Date, Day)>,
+    /// Missing in stores written before daily seeds were secret
+    #[serde(default)]
+    daily_secret: Option<Seed>,
+    #[serde(default)]
+    presets: Vec<(PlayerID, Vec<Preset>)>,
+    #[serde(default)]
+    replays: Vec<(MatchID, Recording)>,
+}
 
 pub struct Store {
-    data: DashMap<SessionID, Session>,
+    sessions: DashMap<SessionID, Session>,
+    matches: DashMap<MatchID, Match>,
     session_lifetime: Duration,
+    backend: Option<Arc<dyn Backend>>,
+    /// Turns true once the server starts shutting down
+    closing: watch::Sender<bool>,
+    pub leaderboard: Leaderboard,
+    pub presets: Presets,
+    pub replays: Replays,
 }
 
-impl<'a> Store {
+fn insert_unique<T>(map: &DashMap<Uuid, T>, value: T) -> Result<Uuid> {
+    let id = Uuid::now_v7();
+
+    match map.entry(id) {
+        Entry::Occupied(_) => bail!("UUID collision?!"),
+        Entry::Vacant(entry) => entry.insert(value),
+    };
+
+    Ok(id)
+}
+
+impl Store {
     pub fn new(session_lifetime: Duration) -> Self {
         Self {
-            data: DashMap::new(),
+            sessions: DashMap::new(),
+            matches: DashMap::new(),
             session_lifetime,
+            backend: None,
+            closing: watch::Sender::new(false),
+            leaderboard: Leaderboard::default(),
+            presets: Presets::default(),
+            replays: Replays::default(),
         }
     }
 
-    fn insert(&'a self, session: Session) -> Result<SessionRefMut<'a>> {
-        let id = SessionID::now_v7();
+    pub fn with_backend(mut self, backend: impl Backend + 'static) -> Self {
+        self.backend.replace(Arc::new(backend));
+        self
+    }
+
+    async fn snapshot(&self) -> StoreSnapshot {
+        let sessions = self
+            .sessions
+            .iter()
+            .map(|entry| (*entry.key(), *entry.value()))
+            .collect();
+
+        // Don't hold the map while waiting for games to be released
+        let entries: Vec<_> = self
+            .matches
+            .iter()
+            .map(|entry| {
+                let id = *entry.key();
+                (
+                    id,
+                    entry.expires,
+                    entry.players,
+                    entry.open,
+                    entry.game.clone(),
+                )
+            })
+            .collect();
 
-        let session_ref = match self.data.entry(id) {
-            Entry::Occupied(_) => bail!("UUID collision?!"),
-            Entry::Vacant(entry) => entry.insert(session),
+        let mut matches = Vec::with_capacity(entries.len());
+        for (id, expires, players, open, game) in entries {
+            let game = game.lock().await.snapshot();
+            matches.push((
+                id,
+                MatchSnapshot {
+                    expires,
+                    players,
+                    open,
+                    game,
+                },
+            ));
+        }
+
+        StoreSnapshot {
+            sessions,
+            matches,
+            leaderboard: self.leaderboard.snapshot(),
+            daily_secret: Some(self.leaderboard.secret()),
+            presets: self.presets.snapshot(),
+            replays: self.replays.snapshot(),
+        }
+    }
+
+    async fn restore(&self, snapshot: StoreSnapshot) {
+        for (id, entry) in snapshot.matches {
+            // A broken game shouldn't take down the rest of the store
+            let game = match Game::restore(&entry.game) {
+                Ok(game) => game,
+                Err(e) => {
+                    tracing::warn!("Skipping game {id}: {e:#}");
+                    continue;
+                }
+            };
+
+            self.matches.insert(
+                id,
+                Match {
+                    expires: entry.expires,
+                    players: entry.players,
+                    open: entry.open,
+                    game: Arc::new(Mutex::new(game)),
+                    notices: Match::channels(),
+                },
+            );
+        }
+
+        for (id, session) in snapshot.sessions {
+            if self.matches.contains_key(&session.match_id) {
+                self.sessions.insert(id, session);
+            }
+        }
+
+        self.leaderboard.restore(snapshot.leaderboard);
+        if let Some(secret) = snapshot.daily_secret {
+            self.leaderboard.set_secret(secret);
+        }
+        self.presets.restore(snapshot.presets);
+        self.replays.restore(snapshot.replays);
+    }
+
+    pub async fn load(&self) -> Result<()> {
+        let Some(backend) = self.backend.clone() else {
+            return Ok(());
         };
 
-        Ok(session_ref)
+        let snapshot = tokio::task::spawn_blocking(move || backend.load()).await??;
+
+        if let Some(snapshot) = snapshot {
+            self.restore(snapshot).await;
+            tracing::info!(
+                "Loaded {} sessions and {} games",
+                self.sessions.len(),
+                self.matches.len()
+            );
+        }
+        Ok(())
     }
 
-    fn get(&'a self, id: &SessionID) -> Option<SessionRef<'a>> {
-        self.data.get(id)
+    pub async fn flush(&self) -> Result<()> {
+        let Some(backend) = self.backend.clone() else {
+            return Ok(());
+        };
+
+        let snapshot = self.snapshot().await;
+        tokio::task::spawn_blocking(move || backend.save(&snapshot)).await??;
+
+        tracing::info!("Flushed board data");
+        Ok(())
     }
 
-    async fn delete(&self, session: SessionRef<'a>) {
-        let id = session.key().clone();
-        drop(session);
-        self.data.remove(&id);
+    /// Ends the event streams, which would otherwise keep the server up, and saves everything
+    pub async fn close(&self) -> Result<()> {
+        self.closing.send_replace(true);
+        self.flush().await
+    }
+
+    /// Tells when the server starts shutting down
+    pub fn closing(&self) -> watch::Receiver<bool> {
+        self.closing.subscribe()
+    }
+
+    // Sits the session at `seat`, failing if someone is already there
+    fn seat(&self, match_id: MatchID, seat: Seat, session: SessionID) -> Result<()> {
+        let mut entry = self
+            .matches
+            .get_mut(&match_id)
+            .ok_or(anyhow!("Game not found"))?;
+
+        let player = &mut entry.players[seat.index()];
+        if player.is_some() {
+            bail!("This seat is already taken");
+        }
+
+        player.replace(session);
+        if seat == Seat::Guest {
+            entry.open = false;
+        }
+        Ok(())
+    }
+
+    fn delete(&self, id: SessionID) {
+        let Some((_, session)) = self.sessions.remove(&id) else {
+            return;
+        };
+
+        let abandoned = match self.matches.get_mut(&session.match_id) {
+            Some(mut entry) => {
+                entry.players[session.seat.index()] = None;
+                entry.players.iter().all(Option::is_none)
+            }
+            None => false,
+        };
+
+        if abandoned {
+            self.matches.remove(&session.match_id);
+        }
+    }
+
+    /// Games waiting for a second player, oldest first
+    pub fn open_matches(&self) -> Vec<MatchID> {
+        let mut open: Vec<MatchID> = self
+            .matches
+            .iter()
+            .filter(|entry| entry.open)
+            .map(|entry| *entry.key())
+            .collect();
+
+        // UUIDv7 are ordered by creation time
+        open.sort();
+        open
     }
 
     async fn cleanup(&self) {
         let now = UtcDateTime::now();
-        self.data.retain(|_, entry| entry.expires >= now);
+        self.sessions.retain(|_, entry| entry.expires >= now);
+        self.matches.retain(|_, entry| entry.expires >= now);
+        self.leaderboard.cleanup();
+        self.replays.cleanup();
 
         tracing::info!("Cleaned up board data")
     }
 
+    pub fn with_flush(self: StoreAccessor, interval: scheduler::Interval) -> StoreAccessor {
+        if self.backend.is_none() {
+            return self;
+        }
+
+        let accessor = self.clone();
+
+        scheduler::schedule_task("Board data flush", interval, move || {
+            let store = accessor.clone();
+            async move {
+                if let Err(e) = store.flush().await {
+                    tracing::error!("Failed to flush board data: {e:#}");
+                }
+            }
+        });
+        self
+    }
+
     pub fn with_cleanup(self: StoreAccessor) -> StoreAccessor {
         // TODO: it might be useful to cleanup more often under high memory pressure
         // or even schedule individual cleanup tasks per session
@@ -91,38 +355,163 @@ impl<'a> Store {
 
 type StoreAccessor = Arc<Store>;
 
+/// Current session along with exclusive access to its game
+pub struct Player {
+    id: SessionID,
+    pub match_id: MatchID,
+    pub seat: Seat,
+    pub game: OwnedMutexGuard<Game>,
+}
+
 pub struct SessionManager {
     store: StoreAccessor,
     cookies: Cookies,
 }
 
-impl<'a> SessionManager {
-    pub fn create(&'a self, board: Board) -> Result<SessionRefMut<'a>> {
-        let now = OffsetDateTime::now_utc();
-        let expires = now + self.store.session_lifetime;
+impl SessionManager {
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
 
-        let session = self.store.insert(Session { expires, board })?;
-        let id = session.key();
+    fn start(&self, match_id: MatchID, seat: Seat, expires: OffsetDateTime) -> Result<SessionID> {
+        let id = insert_unique(
+            &self.store.sessions,
+            Session {
+                expires,
+                match_id,
+                seat,
+            },
+        )?;
 
         self.cookies.add(
             Cookie::build((SESSION_COOKIE_REF, id.to_string()))
+                .path("/")
                 .expires(expires)
                 .build(),
         );
 
         tracing::info!("New session created: {}", id);
-        Ok(session)
+        Ok(id)
+    }
+
+    /// Starts a new game, seating the current client as the host
+    pub async fn create(&self, game: Game, open: bool) -> Result<Player> {
+        let now = OffsetDateTime::now_utc();
+        let expires = now + self.store.session_lifetime;
+
+        let match_id = insert_unique(
+            &self.store.matches,
+            Match {
+                expires,
+                players: [None, None],
+                open,
+                game: Arc::new(Mutex::new(game)),
+                notices: Match::channels(),
+            },
+        )?;
+
+        let id = self.start(match_id, Seat::Host, expires)?;
+        self.store.seat(match_id, Seat::Host, id)?;
+
+        self.player(id).await.ok_or(anyhow!("Session vanished"))
+    }
+
+    /// Seats the current client as the guest of an open game
+    pub async fn join(&self, match_id: MatchID) -> WebResult<Player> {
+        // Opening your own invite link just brings you back to the game
+        if let Some(id) = self.current_id()
+            && let Some(session) = self.store.sessions.get(&id).map(|session| *session)
+            && session.match_id == match_id
+        {
+            return Ok(self.player(id).await.ok_or(anyhow!("Session vanished"))?);
+        }
+
+        let expires = match self.store.matches.get(&match_id) {
+            Some(entry) if entry.open => entry.expires,
+            Some(_) => {
+                return Err(anyhow!("This game is already full")
+                    .client_error()
+                    .code(StatusCode::CONFLICT));
+            }
+            None => {
+                return Err(anyhow!("Game not found")
+                    .client_error()
+                    .code(StatusCode::NOT_FOUND));
+            }
+        };
+
+        let id = self.start(match_id, Seat::Guest, expires)?;
+        self.store
+            .seat(match_id, Seat::Guest, id)
+            .map_err(|e| e.client_error().code(StatusCode::CONFLICT))?;
+
+        Ok(self.player(id).await.ok_or(anyhow!("Session vanished"))?)
     }
 
-    pub fn current(&'a self) -> Option<SessionRef<'a>> {
+    /// Identifies the browser across games, without any sign up
+    pub fn player_id(&self) -> PlayerID {
+        if let Some(id) = self
+            .cookies
+            .get(PLAYER_COOKIE_REF)
+            .and_then(|cookie| cookie.value().parse().ok())
+        {
+            return id;
+        }
+
+        let id = Uuid::now_v7();
+        self.cookies.add(
+            Cookie::build((PLAYER_COOKIE_REF, id.to_string()))
+                .path("/")
+                .expires(OffsetDateTime::now_utc() + Duration::days(365))
+                .build(),
+        );
+        id
+    }
+
+    fn current_id(&self) -> Option<SessionID> {
         // TODO: maybe propagate parse error
-        let id = &self.cookies.get(SESSION_COOKIE_REF)?.value().parse().ok()?;
-        self.store.get(id)
+        self.cookies.get(SESSION_COOKIE_REF)?.value().parse().ok()
+    }
+
+    async fn player(&self, id: SessionID) -> Option<Player> {
+        let session = *self.store.sessions.get(&id)?;
+        let game = self.store.matches.get(&session.match_id)?.game.clone();
+
+        Some(Player {
+            id,
+            match_id: session.match_id,
+            seat: session.seat,
+            game: game.lock_owned().await,
+        })
     }
 
-    pub async fn delete(&'a self, handle: SessionRef<'a>) {
-        self.cookies.remove(SESSION_COOKIE_REF.into());
-        self.store.delete(handle).await;
+    pub async fn current(&self) -> Option<Player> {
+        self.player(self.current_id()?).await
+    }
+
+    /// Notices for the current session, without locking its game
+    pub fn subscribe(&self) -> Option<broadcast::Receiver<Notice>> {
+        let session = *self.store.sessions.get(&self.current_id()?)?;
+        let entry = self.store.matches.get(&session.match_id)?;
+
+        Some(entry.notices[session.seat.index()].subscribe())
+    }
+
+    pub fn notify(&self, match_id: MatchID, seat: Seat, notice: Notice) {
+        if let Some(entry) = self.store.matches.get(&match_id) {
+            // Nobody listening is fine, they'll see the changes on the next request
+            let _ = entry.notices[seat.index()].send(notice);
+        }
+    }
+
+    pub async fn delete(&self, player: Player) {
+        self.cookies
+            .remove(Cookie::build(SESSION_COOKIE_REF).path("/").build());
+
+        // Release the game before its match might get removed
+        let id = player.id;
+        drop(player);
+        self.store.delete(id);
     }
 
     pub fn current_exists(&self) -> bool {
@@ -133,17 +522,25 @@ impl<'a> SessionManager {
     }
 }
 
-pub trait SessionOptionExt<'a> {
-    fn require(self) -> WebResult<SessionRef<'a>>;
+fn session_not_found() -> WebError {
+    anyhow!("Session not found")
+        .client_error()
+        .code(StatusCode::UNAUTHORIZED)
+}
+
+pub trait SessionOptionExt<T> {
+    fn require(self) -> WebResult<T>;
+}
+
+impl SessionOptionExt<Player> for Option<Player> {
+    fn require(self) -> WebResult<Player> {
+        self.ok_or_else(session_not_found)
+    }
 }
 
-impl<'a> SessionOptionExt<'a> for Option<SessionRef<'a>> {
-    fn require(self) -> WebResult<SessionRef<'a>> {
-        self.ok_or(
-            anyhow!("Session not found")
-                .client_error()
-                .code(StatusCode::UNAUTHORIZED),
-        )
+impl SessionOptionExt<broadcast::Receiver<Notice>> for Option<broadcast::Receiver<Notice>> {
+    fn require(self) -> WebResult<broadcast::Receiver<Notice>> {
+        self.ok_or_else(session_not_found)
     }
 }
 
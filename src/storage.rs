@@ -0,0 +1,46 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::session::StoreSnapshot;
+
+/// Somewhere to keep sessions between server restarts
+pub trait Backend: Send + Sync {
+    fn load(&self) -> Result<Option<StoreSnapshot>>;
+    fn save(&self, snapshot: &StoreSnapshot) -> Result<()>;
+}
+
+/// Keeps the whole store in a single JSON file
+pub struct FileBackend {
+    path: PathBuf,
+}
+
+impl FileBackend {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl Backend for FileBackend {
+    fn load(&self) -> Result<Option<StoreSnapshot>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let data = fs::read(&self.path).context("Failed to read store file")?;
+        let snapshot = serde_json::from_slice(&data).context("Failed to parse store file")?;
+
+        Ok(Some(snapshot))
+    }
+
+    fn save(&self, snapshot: &StoreSnapshot) -> Result<()> {
+        let data = serde_json::to_vec(snapshot)?;
+
+        // Write next to the target first, so a crash mid-write can't corrupt the store
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, data).context("Failed to write store file")?;
+        fs::rename(&temporary, &self.path).context("Failed to replace store file")?;
+
+        Ok(())
+    }
+}
@@ -181,12 +181,14 @@ pub mod shutdown {
 }
 
 pub mod htmx {
+    use std::convert::Infallible;
+
     use anyhow::{Context, anyhow};
     use axum::{
         body::Body,
         extract::FromRequestParts,
-        http::{HeaderMap, HeaderName},
-        response::IntoResponse,
+        http::{HeaderName, HeaderValue},
+        response::{IntoResponse, IntoResponseParts, ResponseParts},
     };
     use shrinkwraprs::Shrinkwrap;
 
@@ -194,6 +196,19 @@ pub mod htmx {
 
     use super::errors::WebError;
 
+    fn htmx_header(parts: &axum::http::request::Parts, name: &str) -> Result<String, WebError> {
+        let value = parts
+            .headers
+            .get(name)
+            .ok_or(anyhow!("This handler should be invoked by HTMX").client_error())?
+            .to_str()
+            .with_context(|| format!("Invalid {name} header"))
+            .map_err(|e| e.client_error())?;
+
+        Ok(value.into())
+    }
+
+    /// Id of the element targeted by the request
     #[derive(Shrinkwrap)]
     pub struct HtmxTarget(String);
 
@@ -202,27 +217,49 @@ pub mod htmx {
 
         async fn from_request_parts(
             parts: &mut axum::http::request::Parts,
-            state: &S,
+            _state: &S,
         ) -> Result<Self, Self::Rejection> {
-            let headers = HeaderMap::from_request_parts(parts, state).await.unwrap();
-            let value = headers
-                .get("HX-Target")
-                .ok_or(anyhow!("This handler should be invoked by HTMX").client_error())?
-                .to_str()
-                .context("Invalid target header")
-                .map_err(|e| e.client_error())?;
-
-            Ok(Self(value.into()))
+            Ok(Self(htmx_header(parts, "HX-Target")?))
+        }
+    }
+
+    /// Id of the element that triggered the request
+    #[derive(Shrinkwrap)]
+    pub struct HtmxTrigger(String);
+
+    impl<S: Send + Sync> FromRequestParts<S> for HtmxTrigger {
+        type Rejection = WebError;
+
+        async fn from_request_parts(
+            parts: &mut axum::http::request::Parts,
+            _state: &S,
+        ) -> Result<Self, Self::Rejection> {
+            Ok(Self(htmx_header(parts, "HX-Trigger")?))
+        }
+    }
+
+    /// Keeps the element that made the request in place, only out of band updates are applied
+    pub struct HtmxNoSwap;
+
+    impl IntoResponseParts for HtmxNoSwap {
+        type Error = Infallible;
+
+        fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+            res.headers_mut().insert(
+                HeaderName::from_static("hx-reswap"),
+                HeaderValue::from_static("none"),
+            );
+            Ok(res)
         }
     }
 
     pub struct HtmxRedirect {
-        url: &'static str,
+        url: String,
     }
 
     impl HtmxRedirect {
-        pub fn to(target: &'static str) -> Self {
-            Self { url: target }
+        pub fn to(target: impl Into<String>) -> Self {
+            Self { url: target.into() }
         }
     }
 